@@ -0,0 +1,142 @@
+// Throughput of readers rendering a page of dragons while the refresher keeps
+// swapping the state: the old Mutex<Box<AppState>> against Shared (ArcSwap).
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use dragon_api::config::Config;
+use dragon_api::state::reciver::build_state;
+use dragon_api::state::{AppState, ContractStates, Shared};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DRAGONS: usize = 10_000;
+const PAGE: usize = 6;
+const READERS: [usize; 3] = [1, 4, 8];
+
+fn synthetic_state() -> AppState {
+    let mut states = ContractStates::default();
+    let main = &mut states.main;
+    for id in 1..=DRAGONS {
+        let (id, owner) = (id.to_string(), format!("0x{:040x}", id % 100));
+        main.token_gen_image
+            .insert(id.clone(), String::from("77703034331143344117314110158"));
+        main.token_gen_battle.insert(
+            id.clone(),
+            String::from(
+                "17213176947417029247062885245301688801479160274101322991103071845030308089925",
+            ),
+        );
+        main.token_stage.insert(id.clone(), String::from("1"));
+        main.token_uris
+            .insert(id.clone(), format!("https://res.cloudinary.com/{}.png", id));
+        main.tokens_owner_stage
+            .entry(owner.clone())
+            .or_default()
+            .insert(id.clone(), String::from("1"));
+        main.token_owners.insert(id, owner);
+    }
+    build_state(states, Default::default()).unwrap()
+}
+
+// What a list handler does with the state: look up a page and serialize it.
+fn render_page(state: &AppState, n: usize) -> usize {
+    let start = (n * PAGE) % (state.all_id_list.len() - PAGE);
+    let page: Vec<_> = state.all_id_list[start..start + PAGE]
+        .iter()
+        .map(|id| {
+            (
+                id,
+                &state.all_id_owner[id],
+                &state.main_state.token_uris[id],
+                &state.main_state.token_gen_image[id],
+                state.all_id_rarity[id],
+            )
+        })
+        .collect();
+    serde_json::to_string(&page).unwrap().len()
+}
+
+// Runs `readers` threads doing `iters` reads in total while `swap` runs in a loop.
+fn run(
+    readers: usize,
+    iters: u64,
+    read: Arc<dyn Fn(usize) -> usize + Send + Sync>,
+    swap: Arc<dyn Fn() + Send + Sync>,
+) -> Duration {
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                swap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    };
+    let per_reader = (iters as usize).div_ceil(readers);
+    let start = Instant::now();
+    let handles: Vec<_> = (0..readers)
+        .map(|r| {
+            let read = Arc::clone(&read);
+            thread::spawn(move || {
+                let mut sum = 0;
+                for n in 0..per_reader {
+                    sum += read(r * per_reader + n);
+                }
+                sum
+            })
+        })
+        .collect();
+    for handle in handles {
+        criterion::black_box(handle.join().unwrap());
+    }
+    let elapsed = start.elapsed();
+    stop.store(true, Ordering::Relaxed);
+    writer.join().unwrap();
+    elapsed
+}
+
+fn concurrent_reads(c: &mut Criterion) {
+    let state = synthetic_state();
+    let next = Arc::new(state.clone());
+    let mut group = c.benchmark_group("concurrent_reads");
+    group.throughput(Throughput::Elements(1));
+    for readers in READERS {
+        let mutex = Arc::new(Mutex::new(Box::new(state.clone())));
+        let (reader_mutex, writer_mutex) = (Arc::clone(&mutex), Arc::clone(&mutex));
+        let next_mutex = Arc::clone(&next);
+        group.bench_with_input(BenchmarkId::new("mutex", readers), &readers, |b, &r| {
+            let read: Arc<dyn Fn(usize) -> usize + Send + Sync> = {
+                let m = Arc::clone(&reader_mutex);
+                Arc::new(move |n| render_page(&m.lock().unwrap(), n))
+            };
+            // swap with a spare prebuilt state, only the lock is measured
+            let swap: Arc<dyn Fn() + Send + Sync> = {
+                let m = Arc::clone(&writer_mutex);
+                let spare = Mutex::new(Box::new((*next_mutex).clone()));
+                Arc::new(move || {
+                    let mut spare = spare.lock().unwrap();
+                    std::mem::swap(&mut *m.lock().unwrap(), &mut *spare);
+                })
+            };
+            b.iter_custom(|iters| run(r, iters, Arc::clone(&read), Arc::clone(&swap)));
+        });
+
+        let shared = Shared::new(Config::default(), state.clone());
+        group.bench_with_input(BenchmarkId::new("arc_swap", readers), &readers, |b, &r| {
+            let read: Arc<dyn Fn(usize) -> usize + Send + Sync> = {
+                let s = Arc::clone(&shared);
+                Arc::new(move |n| render_page(&s.app_state.load_full(), n))
+            };
+            let swap: Arc<dyn Fn() + Send + Sync> = {
+                let (s, next) = (Arc::clone(&shared), Arc::clone(&next));
+                Arc::new(move || s.app_state.store(Arc::clone(&next)))
+            };
+            b.iter_custom(|iters| run(r, iters, Arc::clone(&read), Arc::clone(&swap)));
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, concurrent_reads);
+criterion_main!(benches);
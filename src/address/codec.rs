@@ -0,0 +1,92 @@
+use crate::address::{Address, AddressError, ADDRESS_LEN, BECH32_HRP};
+use bech32::{FromBase32, ToBase32, Variant};
+use sha2::{Digest, Sha256};
+
+impl Address {
+    // 0x hex (any case, mixed case must be checksummed) or zil1 bech32
+    pub fn parse(text: &str) -> Result<Address, AddressError> {
+        let text = text.trim();
+        if text
+            .get(..4)
+            .is_some_and(|x| x.eq_ignore_ascii_case("zil1"))
+        {
+            return Address::from_bech32(text);
+        }
+        let digits = text
+            .strip_prefix("0x")
+            .or_else(|| text.strip_prefix("0X"))
+            .unwrap_or(text);
+        if digits.len() != ADDRESS_LEN * 2 || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(AddressError::Format);
+        }
+        let mut bytes = [0u8; ADDRESS_LEN];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                .map_err(|_| AddressError::Format)?;
+        }
+        let address = Address(bytes);
+        let has_lower = digits.bytes().any(|b| b.is_ascii_lowercase());
+        let has_upper = digits.bytes().any(|b| b.is_ascii_uppercase());
+        if has_lower && has_upper && address.to_checksum()[2..] != *digits {
+            return Err(AddressError::Checksum);
+        }
+        Ok(address)
+    }
+    fn from_bech32(text: &str) -> Result<Address, AddressError> {
+        let (hrp, data, variant) =
+            bech32::decode(text).map_err(|e| AddressError::Bech32(e.to_string()))?;
+        if hrp != BECH32_HRP || variant != Variant::Bech32 {
+            return Err(AddressError::Bech32(format!("unexpected prefix {}", hrp)));
+        }
+        let bytes =
+            Vec::<u8>::from_base32(&data).map_err(|e| AddressError::Bech32(e.to_string()))?;
+        let bytes: [u8; ADDRESS_LEN] = bytes
+            .try_into()
+            .map_err(|_| AddressError::Bech32("wrong length".to_string()))?;
+        Ok(Address(bytes))
+    }
+    // the form of the contract state keys: 0x and lower case
+    pub fn to_hex(&self) -> String {
+        let mut result = String::with_capacity(2 + ADDRESS_LEN * 2);
+        result.push_str("0x");
+        for byte in self.0 {
+            result.push_str(&format!("{:02x}", byte));
+        }
+        result
+    }
+    pub fn to_bech32(&self) -> String {
+        bech32::encode(BECH32_HRP, self.0.to_base32(), Variant::Bech32)
+            .unwrap_or_else(|_| self.to_hex())
+    }
+    // Zilliqa checksum: a letter is upper case when bit 255 - 6 * i of sha256(address) is set
+    pub fn to_checksum(&self) -> String {
+        let hash = Sha256::digest(self.0);
+        let hex = self.to_hex();
+        let mut result = String::with_capacity(hex.len());
+        result.push_str("0x");
+        for (i, c) in hex[2..].chars().enumerate() {
+            let bit = hash[6 * i / 8] & (0x80 >> (6 * i % 8));
+            if c.is_ascii_alphabetic() && bit != 0 {
+                result.push(c.to_ascii_uppercase());
+            } else {
+                result.push(c);
+            }
+        }
+        result
+    }
+}
+
+// canonical 0x lower case form of an address from a request
+pub fn normalize(text: &str) -> Result<String, AddressError> {
+    Address::parse(text).map(|x| x.to_hex())
+}
+
+// Same for the contract states, keeps what doesn't parse as is (lower case).
+pub fn canonical(text: &str) -> String {
+    normalize(text).unwrap_or_else(|_| text.to_lowercase())
+}
+
+// zil1... form of a canonical address
+pub fn to_bech32(text: &str) -> String {
+    Address::parse(text).map_or_else(|_| text.to_string(), |x| x.to_bech32())
+}
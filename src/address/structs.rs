@@ -0,0 +1,29 @@
+use std::fmt;
+
+pub const ADDRESS_LEN: usize = 20;
+pub const BECH32_HRP: &str = "zil";
+
+// ByStr20 of a Zilliqa account
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct Address(pub [u8; ADDRESS_LEN]);
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AddressError {
+    // neither 40 hex digits nor a zil1 bech32 string
+    Format,
+    // mixed case hex that isn't a valid checksum
+    Checksum,
+    Bech32(String),
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AddressError::Format => write!(f, "expected 0x and 40 hex digits or zil1..."),
+            AddressError::Checksum => write!(f, "bad checksum"),
+            AddressError::Bech32(e) => write!(f, "bad bech32: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
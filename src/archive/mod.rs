@@ -0,0 +1,6 @@
+pub mod structs;
+pub use structs::*;
+pub mod store;
+pub use store::{load, read, remove, save};
+pub mod retention;
+pub use retention::{compact, lock, record};
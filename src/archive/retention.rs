@@ -0,0 +1,92 @@
+use crate::archive::{remove, save, Archive, Checkpoint};
+use crate::config::Config;
+use crate::state::Shared;
+use std::io::Error;
+use std::sync::{Arc, MutexGuard};
+use tokio::task::spawn_blocking;
+
+pub fn lock(shared: &Shared) -> MutexGuard<'_, Archive> {
+    shared
+        .archive
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+// Makes the state just swapped in the live one, archives it at most once
+// per archive_interval_secs and drops what the retention window doesn't keep.
+pub async fn record(shared: &Shared, block: u128, time: u64) {
+    let config = &shared.config;
+    let checkpoint = Checkpoint { block, time };
+    let state = shared.app_state.load_full();
+    let due = {
+        let mut archive = lock(shared);
+        archive.live = Some((checkpoint, Arc::clone(&state)));
+        !config.archive_path.is_empty()
+            && archive
+                .checkpoints
+                .last()
+                .is_none_or(|x| time >= x.time + config.archive_interval_secs)
+    };
+    if !due {
+        return;
+    }
+    // written without the lock and off the runtime, the readers of older
+    // states go on meanwhile
+    let dir = config.archive_path.clone();
+    let saved = spawn_blocking(move || save(&dir, checkpoint, &state)).await;
+    if let Err(e) = saved.unwrap_or_else(|e| Err(Error::other(e))) {
+        eprintln!("Cannot archive block {}: {}", block, e);
+        return;
+    }
+    let removed = {
+        let mut archive = lock(shared);
+        archive.checkpoints.push(checkpoint);
+        let removed = compact(&mut archive.checkpoints, time, config);
+        if archive
+            .cached
+            .as_ref()
+            .is_some_and(|(x, _)| removed.contains(x))
+        {
+            archive.cached = None;
+        }
+        removed
+    };
+    let dir = config.archive_path.clone();
+    let removing = spawn_blocking(move || {
+        for checkpoint in removed {
+            if let Err(e) = remove(&dir, checkpoint) {
+                eprintln!("Cannot remove archived block {}: {}", checkpoint.block, e);
+            }
+        }
+    });
+    if let Err(e) = removing.await {
+        eprintln!("Cannot remove archived blocks: {}", e);
+    }
+}
+
+// Keeps every checkpoint of the last archive_recent_secs, the last one of
+// each archive_compact_secs before, nothing older than archive_retention_secs.
+// Returns the dropped ones.
+pub fn compact(checkpoints: &mut Vec<Checkpoint>, now: u64, config: &Config) -> Vec<Checkpoint> {
+    let recent = now.saturating_sub(config.archive_recent_secs);
+    let bucket = |x: &Checkpoint| x.time / config.archive_compact_secs.max(1);
+    let expired = |x: &Checkpoint| {
+        config.archive_retention_secs != 0
+            && x.time < now.saturating_sub(config.archive_retention_secs)
+    };
+    let mut kept = Vec::with_capacity(checkpoints.len());
+    let mut removed = Vec::new();
+    for (i, checkpoint) in checkpoints.iter().enumerate() {
+        let thinned = checkpoint.time < recent
+            && checkpoints
+                .get(i + 1)
+                .is_some_and(|next| next.time < recent && bucket(next) == bucket(checkpoint));
+        if thinned || expired(checkpoint) {
+            removed.push(*checkpoint);
+        } else {
+            kept.push(*checkpoint);
+        }
+    }
+    *checkpoints = kept;
+    removed
+}
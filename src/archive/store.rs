@@ -0,0 +1,91 @@
+use crate::archive::Checkpoint;
+use crate::state::reciver::build_state;
+use crate::state::{AppState, ContractStates, EventHistory};
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+// Bump on any change of ContractStates or EventHistory layout, older
+// archives are not read then.
+pub const ARCHIVE_VERSION: u32 = 1;
+
+// Only what AppState is built from, the indexes and stats derived from it
+// are built again on read instead of being stored with every checkpoint.
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    version: u32,
+    block: u128,
+    states: ContractStates,
+    history: EventHistory,
+}
+
+#[derive(Deserialize)]
+struct EntryHeader {
+    version: u32,
+}
+
+fn file_path(dir: &str, checkpoint: Checkpoint) -> String {
+    Path::new(dir)
+        .join(format!("{}-{}.json", checkpoint.block, checkpoint.time))
+        .to_string_lossy()
+        .into_owned()
+}
+
+// Written to a temp file and renamed, a crash never leaves a torn one.
+pub fn save(dir: &str, checkpoint: Checkpoint, state: &AppState) -> Result<(), Error> {
+    fs::create_dir_all(dir)?;
+    let path = file_path(dir, checkpoint);
+    let tmp_path = format!("{}.tmp", path);
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    let entry = Entry {
+        version: ARCHIVE_VERSION,
+        block: checkpoint.block,
+        states: state.contract_states(),
+        history: state.history(),
+    };
+    serde_json::to_writer(&mut writer, &entry).map_err(Error::other)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, path)
+}
+
+// None if it was archived by another version.
+pub fn read(dir: &str, checkpoint: Checkpoint) -> Result<Option<AppState>, Error> {
+    let text = fs::read_to_string(file_path(dir, checkpoint))?;
+    let header: EntryHeader =
+        serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if header.version != ARCHIVE_VERSION {
+        return Ok(None);
+    }
+    let entry: Entry =
+        serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    build_state(entry.states, entry.history)
+        .map(Some)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))
+}
+
+pub fn remove(dir: &str, checkpoint: Checkpoint) -> Result<(), Error> {
+    fs::remove_file(file_path(dir, checkpoint))
+}
+
+// Checkpoints of the archived states, oldest first, taken from the file names.
+pub fn load(dir: &str) -> Result<Vec<Checkpoint>, Error> {
+    if dir.is_empty() || !Path::new(dir).exists() {
+        return Ok(Vec::new());
+    }
+    let mut checkpoints = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name();
+        let parsed = name
+            .to_str()
+            .and_then(|x| x.strip_suffix(".json"))
+            .and_then(|x| x.split_once('-'))
+            .and_then(|(block, time)| Some((block.parse().ok()?, time.parse().ok()?)));
+        // temp files of an interrupted save and anything else are left alone
+        if let Some((block, time)) = parsed {
+            checkpoints.push(Checkpoint { block, time });
+        }
+    }
+    checkpoints.sort_by_key(|x| x.block);
+    Ok(checkpoints)
+}
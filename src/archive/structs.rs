@@ -0,0 +1,66 @@
+use crate::state::AppState;
+use std::sync::Arc;
+
+// An archived AppState, saved as <archive_path>/<block>-<time>.json
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Checkpoint {
+    pub block: u128,
+    pub time: u64, // unix seconds of the refresh
+}
+
+// ?at_block= or ?at_time= of a read endpoint
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum At {
+    Block(u128),
+    Time(u64),
+}
+
+impl At {
+    // the checkpoint was made at or before this point
+    pub fn reaches(&self, checkpoint: &Checkpoint) -> bool {
+        match *self {
+            At::Block(block) => checkpoint.block <= block,
+            At::Time(time) => checkpoint.time <= time,
+        }
+    }
+}
+
+// A state in memory, or one to be read from the archive
+pub enum Found {
+    State(Arc<AppState>),
+    Stored(Checkpoint),
+}
+
+#[derive(Default)]
+pub struct Archive {
+    pub checkpoints: Vec<Checkpoint>, // oldest first
+    // of the state being served, unknown until the first refresh
+    pub live: Option<(Checkpoint, Arc<AppState>)>,
+    // the last one read, asked about again by the next pages
+    pub cached: Option<(Checkpoint, Arc<AppState>)>,
+}
+
+impl Archive {
+    pub fn new(checkpoints: Vec<Checkpoint>) -> Self {
+        Self {
+            checkpoints,
+            ..Default::default()
+        }
+    }
+    // the last checkpoint at or before `at`, the live state included
+    pub fn resolve(&self, at: At) -> Option<Found> {
+        if let Some((checkpoint, state)) = &self.live {
+            if at.reaches(checkpoint) {
+                return Some(Found::State(Arc::clone(state)));
+            }
+        }
+        let count = self.checkpoints.partition_point(|x| at.reaches(x));
+        let found = self.checkpoints[..count].last()?;
+        match &self.cached {
+            Some((checkpoint, state)) if checkpoint == found => {
+                Some(Found::State(Arc::clone(state)))
+            }
+            _ => Some(Found::Stored(*found)),
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::config::*;
+use std::fmt::Display;
+use std::io::{Error, ErrorKind};
+use std::path::Path;
+use std::str::FromStr;
+
+// Defaults <- config file (CONFIG_FILE or ./config.json) <- env variables
+pub fn load() -> Result<Config, Error> {
+    let path = match std::env::var("CONFIG_FILE") {
+        Ok(val) => val,
+        Err(_) => String::from(DEFAULT_CONFIG_FILE),
+    };
+    let mut config = if Path::new(&path).exists() {
+        from_file(&path)?
+    } else {
+        Config::default()
+    };
+    apply_env(&mut config)?;
+    Ok(config)
+}
+pub fn from_file(path: &str) -> Result<Config, Error> {
+    let text = std::fs::read_to_string(path)?;
+    serde_json::from_str(&text)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", path, e)))
+}
+fn apply_env(config: &mut Config) -> Result<(), Error> {
+    let fields = [
+        ("API_URL", &mut config.api_url),
+        ("RPC_URL", &mut config.rpc_url),
+        ("APOLLO_URL", &mut config.apollo_url),
+        ("SNAPSHOT_PATH", &mut config.snapshot_path),
+        ("WEBHOOKS_PATH", &mut config.webhooks_path),
+        ("SALES_PATH", &mut config.sales_path),
+        ("ARCHIVE_PATH", &mut config.archive_path),
+        ("MAIN_CONTRACT", &mut config.contracts.main),
+        ("BATTLE_CONTRACT", &mut config.contracts.battle),
+        ("FIGHT_CONTRACT", &mut config.contracts.fight),
+        ("BREED_CONTRACT", &mut config.contracts.breed),
+        ("MARKET_CONTRACT", &mut config.contracts.market),
+        ("NAME_CONTRACT", &mut config.contracts.name),
+    ];
+    for (name, field) in fields {
+        if let Ok(val) = std::env::var(name) {
+            *field = val;
+        }
+    }
+    parse_env("RPC_RETRIES", &mut config.rpc_retries)?;
+    parse_env("APOLLO_PAGE_SIZE", &mut config.apollo_page_size)?;
+    parse_env("INCREMENTAL", &mut config.incremental)?;
+    parse_env("MAX_INCREMENTAL_BLOCKS", &mut config.max_incremental_blocks)?;
+    parse_env("FULL_RESYNC_SECS", &mut config.full_resync_secs)?;
+    parse_env("MAX_BATCH_SIZE", &mut config.max_batch_size)?;
+    parse_env("WEBHOOK_MAX_ATTEMPTS", &mut config.webhook_max_attempts)?;
+    parse_env("WEBHOOK_RETRY_SECS", &mut config.webhook_retry_secs)?;
+    parse_env("WEBHOOK_TIMEOUT_SECS", &mut config.webhook_timeout_secs)?;
+    parse_env("WEBHOOK_ALLOW_PRIVATE", &mut config.webhook_allow_private)?;
+    parse_env("ARCHIVE_INTERVAL_SECS", &mut config.archive_interval_secs)?;
+    parse_env("ARCHIVE_RECENT_SECS", &mut config.archive_recent_secs)?;
+    parse_env("ARCHIVE_COMPACT_SECS", &mut config.archive_compact_secs)?;
+    parse_env("ARCHIVE_RETENTION_SECS", &mut config.archive_retention_secs)?;
+    Ok(())
+}
+fn parse_env<T>(name: &str, field: &mut T) -> Result<(), Error>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if let Ok(val) = std::env::var(name) {
+        *field = val
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", name, e)))?;
+    }
+    Ok(())
+}
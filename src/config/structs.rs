@@ -0,0 +1,117 @@
+pub const DEFAULT_API_URL: &str = "127.0.0.1:8083";
+pub const DEFAULT_RPC_URL: &str = "https://api.zilliqa.com/";
+pub const DEFAULT_APOLLO_URL: &str = "https://devex-apollo.zilliqa.com/";
+pub const DEFAULT_RPC_RETRIES: u32 = 5;
+pub const DEFAULT_APOLLO_PAGE_SIZE: u64 = 500;
+pub const DEFAULT_SNAPSHOT_PATH: &str = "snapshot.json";
+pub const DEFAULT_MAX_INCREMENTAL_BLOCKS: u64 = 100;
+pub const DEFAULT_FULL_RESYNC_SECS: u64 = 3600;
+pub const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+pub const DEFAULT_WEBHOOKS_PATH: &str = "webhooks.json";
+pub const DEFAULT_SALES_PATH: &str = "sales.jsonl";
+pub const DEFAULT_ARCHIVE_PATH: &str = "archive";
+pub const DEFAULT_ARCHIVE_INTERVAL_SECS: u64 = 600;
+pub const DEFAULT_ARCHIVE_RECENT_SECS: u64 = 86400;
+pub const DEFAULT_ARCHIVE_COMPACT_SECS: u64 = 86400;
+pub const DEFAULT_ARCHIVE_RETENTION_SECS: u64 = 2592000;
+pub const DEFAULT_WEBHOOK_MAX_ATTEMPTS: u32 = 8;
+pub const DEFAULT_WEBHOOK_RETRY_SECS: u64 = 10;
+pub const DEFAULT_WEBHOOK_TIMEOUT_SECS: u64 = 5;
+pub const DEFAULT_CONFIG_FILE: &str = "config.json";
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub api_url: String,
+    // Zilliqa JSON-RPC node
+    pub rpc_url: String,
+    // Devex Apollo GraphQL (transactions and event logs)
+    pub apollo_url: String,
+    // attempts per node request before the refresh is given up
+    pub rpc_retries: u32,
+    // txs per Apollo txPagination request
+    pub apollo_page_size: u64,
+    // AppState saved after every refresh and loaded on start, empty to disable
+    pub snapshot_path: String,
+    // apply the events of new blocks to the current state instead of a full download
+    pub incremental: bool,
+    // larger block gaps are caught up with a full download
+    pub max_incremental_blocks: u64,
+    // full download at least this often, even when nothing looks wrong
+    pub full_resync_secs: u64,
+    // ids per ?ids= or POST /dragons/batch request
+    pub max_batch_size: usize,
+    // registrations and undelivered payloads, empty keeps them in memory only
+    pub webhooks_path: String,
+    // a payload is dropped after this many failed deliveries
+    pub webhook_max_attempts: u32,
+    // first retry delay, doubled on every next one
+    pub webhook_retry_secs: u64,
+    pub webhook_timeout_secs: u64,
+    // let webhooks call loopback and private addresses, for local setups only
+    pub webhook_allow_private: bool,
+    // filled market orders, appended to, empty keeps them in memory only
+    pub sales_path: String,
+    // dir of past AppStates for ?at_block= and ?at_time=, empty to disable
+    pub archive_path: String,
+    // a refresh is archived when this much passed since the last archived one
+    pub archive_interval_secs: u64,
+    // older ones are thinned out to one per archive_compact_secs
+    pub archive_recent_secs: u64,
+    pub archive_compact_secs: u64,
+    // older ones are deleted, 0 keeps them forever
+    pub archive_retention_secs: u64,
+    pub contracts: Contracts,
+}
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            api_url: String::from(DEFAULT_API_URL),
+            rpc_url: String::from(DEFAULT_RPC_URL),
+            apollo_url: String::from(DEFAULT_APOLLO_URL),
+            rpc_retries: DEFAULT_RPC_RETRIES,
+            apollo_page_size: DEFAULT_APOLLO_PAGE_SIZE,
+            snapshot_path: String::from(DEFAULT_SNAPSHOT_PATH),
+            incremental: true,
+            max_incremental_blocks: DEFAULT_MAX_INCREMENTAL_BLOCKS,
+            full_resync_secs: DEFAULT_FULL_RESYNC_SECS,
+            max_batch_size: DEFAULT_MAX_BATCH_SIZE,
+            webhooks_path: String::from(DEFAULT_WEBHOOKS_PATH),
+            webhook_max_attempts: DEFAULT_WEBHOOK_MAX_ATTEMPTS,
+            webhook_retry_secs: DEFAULT_WEBHOOK_RETRY_SECS,
+            webhook_timeout_secs: DEFAULT_WEBHOOK_TIMEOUT_SECS,
+            webhook_allow_private: false,
+            sales_path: String::from(DEFAULT_SALES_PATH),
+            archive_path: String::from(DEFAULT_ARCHIVE_PATH),
+            archive_interval_secs: DEFAULT_ARCHIVE_INTERVAL_SECS,
+            archive_recent_secs: DEFAULT_ARCHIVE_RECENT_SECS,
+            archive_compact_secs: DEFAULT_ARCHIVE_COMPACT_SECS,
+            archive_retention_secs: DEFAULT_ARCHIVE_RETENTION_SECS,
+            contracts: Default::default(),
+        }
+    }
+}
+
+// https://github.com/DeepDragons/DragonZILContracts
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct Contracts {
+    pub main: String,   //   DragonZIL.scilla
+    pub battle: String, //   FightPlace.scilla (waiting_list, wounded_list)
+    pub fight: String,  //   source of AfterFightWinLose events
+    pub breed: String,  //   BreedPlace.scilla
+    pub market: String, //   MarketPlace.scilla
+    pub name: String,   //   dragons_name
+}
+impl Default for Contracts {
+    fn default() -> Self {
+        Self {
+            main: String::from("0xb4d83becb950c096b001a3d1c7abb10f571ae75f"),
+            battle: String::from("0xf0a3fbcfa48e4c796daafbb9c6341d68ff326b64"),
+            fight: String::from("0x21b870dc77921b21f9a98a732786bf812888193c"),
+            breed: String::from("0xade7886ec4a36cb0a7de2f5d18cc7bdae12e3650"),
+            market: String::from("0x7b9b80aaf561ecd4e89ea55d83d59ab7ac01a575"),
+            name: String::from("0x0f5d8f74817e2bc5a09521149094a7860c691d42"),
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use crate::events::{ChangeEvent, EventKind, ListKind};
+use crate::state::{AppState, HMStrings};
+use tokio::sync::broadcast::Sender;
+
+// Changes from old to new, ordered by dragon id and kind.
+pub fn diff(old: &AppState, new: &AppState) -> Vec<ChangeEvent> {
+    let mut events = Vec::new();
+    let owner = |id: &str| {
+        new.all_id_owner
+            .get(id)
+            .or_else(|| old.all_id_owner.get(id))
+            .cloned()
+            .unwrap_or_default()
+    };
+    let mut push = |kind, id: &str, list, from: Option<&String>, to: Option<&String>| {
+        events.push(ChangeEvent {
+            kind,
+            id: id.to_string(),
+            version: new.version,
+            owner: owner(id),
+            list,
+            from: from.cloned(),
+            to: to.cloned(),
+        })
+    };
+    let (old_stages, new_stages) = (&old.main_state.token_stage, &new.main_state.token_stage);
+    for (id, stage) in new_stages {
+        let new_owner = new.all_id_owner.get(id);
+        match old_stages.get(id) {
+            None => push(EventKind::Mint, id, None, None, new_owner),
+            Some(old_stage) => {
+                let old_owner = old.all_id_owner.get(id);
+                if old_owner != new_owner {
+                    push(EventKind::Transfer, id, None, old_owner, new_owner);
+                }
+                if old_stage != stage {
+                    push(
+                        EventKind::StageChanged,
+                        id,
+                        None,
+                        Some(old_stage),
+                        Some(stage),
+                    );
+                }
+            }
+        }
+    }
+    for id in old_stages.keys().filter(|id| !new_stages.contains_key(*id)) {
+        push(EventKind::Burn, id, None, old.all_id_owner.get(id), None);
+    }
+    let lists: [(ListKind, &HMStrings, &HMStrings); 3] = [
+        (ListKind::Market, &old.market_id_price, &new.market_id_price),
+        (ListKind::Battle, &old.battle_id_price, &new.battle_id_price),
+        (ListKind::Breed, &old.breed_id_price, &new.breed_id_price),
+    ];
+    for (list, old_prices, new_prices) in lists {
+        for (id, price) in new_prices {
+            match old_prices.get(id) {
+                None => push(EventKind::Listed, id, Some(list), None, Some(price)),
+                Some(old_price) if old_price != price => push(
+                    EventKind::PriceChanged,
+                    id,
+                    Some(list),
+                    Some(old_price),
+                    Some(price),
+                ),
+                _ => {}
+            }
+        }
+        for (id, price) in old_prices {
+            if !new_prices.contains_key(id) {
+                push(EventKind::Delisted, id, Some(list), Some(price), None);
+            }
+        }
+    }
+    for (id, wounds) in &new.all_id_wounds {
+        let old_wounds = old.all_id_wounds.get(id);
+        for wound in wounds {
+            if !old_wounds.is_some_and(|x| x.contains(wound)) {
+                push(EventKind::Wounded, id, None, None, Some(wound));
+            }
+        }
+    }
+    for (id, name) in &new.id_name {
+        let old_name = old.id_name.get(id);
+        if old_name != Some(name) {
+            push(EventKind::Renamed, id, None, old_name, Some(name));
+        }
+    }
+    events.sort_by(|a, b| {
+        let key = |x: &ChangeEvent| (x.id.parse::<u128>().unwrap_or(u128::MAX), x.kind, x.list);
+        key(a).cmp(&key(b))
+    });
+    events
+}
+
+// Sends the changes to the subscribers, if there are any.
+pub fn publish(sender: &Sender<ChangeEvent>, events: Vec<ChangeEvent>) {
+    if sender.receiver_count() == 0 {
+        return;
+    }
+    for event in events {
+        // fails only when everyone has gone meanwhile
+        let _ = sender.send(event);
+    }
+}
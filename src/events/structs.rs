@@ -0,0 +1,88 @@
+use std::str::FromStr;
+
+// Missed events are dropped for slow subscribers, see Lagged.
+pub const EVENTS_BUFFER: usize = 1024;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Mint,
+    Burn,
+    Transfer,
+    StageChanged,
+    Listed,
+    Delisted,
+    PriceChanged,
+    Wounded,
+    Renamed,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventKind::Mint => "mint",
+            EventKind::Burn => "burn",
+            EventKind::Transfer => "transfer",
+            EventKind::StageChanged => "stage_changed",
+            EventKind::Listed => "listed",
+            EventKind::Delisted => "delisted",
+            EventKind::PriceChanged => "price_changed",
+            EventKind::Wounded => "wounded",
+            EventKind::Renamed => "renamed",
+        }
+    }
+}
+
+impl FromStr for EventKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_value(serde_json::Value::String(s.to_string()))
+            .map_err(|_| format!("unknown event kind {}", s))
+    }
+}
+
+// the waiting list or orderbook of the listing events
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ListKind {
+    Market,
+    Battle,
+    Breed,
+}
+
+// One change of a dragon between two state versions, from and to are owners,
+// stages, prices or names depending on the kind, to of Wounded is the new wound.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct ChangeEvent {
+    pub kind: EventKind,
+    pub id: String,
+    pub version: u64,
+    pub owner: String, // the current one, the last one for Burn
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub list: Option<ListKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<String>,
+}
+
+// What a subscriber asked for, empty matches everything.
+#[derive(Clone, Debug, Default)]
+pub struct EventFilter {
+    pub owner: String, // canonical address
+    pub ids: Vec<String>,
+    pub kinds: Vec<EventKind>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, event: &ChangeEvent) -> bool {
+        // the previous owner follows its transfers too
+        let owner_matches = self.owner.is_empty()
+            || event.owner == self.owner
+            || (event.kind == EventKind::Transfer
+                && event.from.as_deref() == Some(self.owner.as_str()));
+        owner_matches
+            && (self.ids.is_empty() || self.ids.contains(&event.id))
+            && (self.kinds.is_empty() || self.kinds.contains(&event.kind))
+    }
+}
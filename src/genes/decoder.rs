@@ -0,0 +1,125 @@
+use crate::genes::*;
+
+// https://github.com/DeepDragons/dragon-zil/blob/master/src/mixins/utils.js#L50
+// most of visual gens have 2 parts - type (0-9) and color(0-4)
+// head have 1 digit
+// claws have 1 digit
+// Color Scheme have 3 digits
+// MutagenImutable have 3 digits
+// e.g. 777 03 03 43 31 14 33 44 11 73 1 4 110 158
+//      777 01 64 02 94 03 04 40 24 11 4 1 076 065
+// Aura-12   Horns-11   Scales-10   Spots-9   Tail-8   Wings-7
+// Spins-6   Body-5   Eyes-4   Head-3   Claws-2   Color Scheme-1   MutagenImutable-0
+pub fn decode_image(gen_image: &str) -> Option<ImageGenes> {
+    if gen_image.len() < GEN_IMAGE_LEN || !gen_image.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let num = |a: usize, b: usize| gen_image[a..b].parse::<u16>().unwrap_or(0);
+    // unknown (newer) gen types count as None
+    let ri = |table: &[u8], kind: u16| *table.get(kind as usize).unwrap_or(&0);
+    let pair = |at: usize, table: Option<&[u8]>| {
+        let kind = num(at, at + 1);
+        Gene {
+            kind,
+            color: Some(num(at + 1, at + 2) as u8),
+            rarity: table.map(|t| ri(t, kind)),
+        }
+    };
+    let single = |a: usize, b: usize, table: Option<&[u8]>| {
+        let kind = num(a, b);
+        Gene {
+            kind,
+            color: None,
+            rarity: table.map(|t| ri(t, kind)),
+        }
+    };
+    let mut genes = ImageGenes {
+        aura: pair(3, Some(&RI.aura)),
+        horns: pair(5, Some(&RI.horns)),
+        scales: pair(7, Some(&RI.scales)),
+        spots: pair(9, Some(&RI.spots)),
+        tail: pair(11, Some(&RI.tail)),
+        wings: pair(13, Some(&RI.wings)),
+        spins: pair(15, None),
+        body: pair(17, Some(&RI.body)),
+        eyes: pair(19, Some(&RI.eyes)),
+        head: single(21, 22, Some(&RI.head)),
+        claws: single(22, 23, None),
+        color_scheme: single(23, 26, None),
+        mutagen: single(26, 29, None),
+        rarity_sum: 0,
+        rarity: 0,
+    };
+    genes.rarity_sum = [
+        &genes.aura,
+        &genes.horns,
+        &genes.scales,
+        &genes.spots,
+        &genes.tail,
+        &genes.wings,
+        &genes.body,
+        &genes.eyes,
+        &genes.head,
+    ]
+    .iter()
+    .filter_map(|gene| gene.rarity)
+    .map(u16::from)
+    .sum();
+    genes.rarity = rarity_class(genes.rarity_sum);
+    Some(genes)
+}
+/* https://github.com/DeepDragons/dragon-zil/blob/master/src/mixins/utils.js#L1
+ * None      0
+ * Common    1
+ * Uncommon  2
+ * Rare      3
+ * Mythical  4
+ * Legendary 5
+ * Immortal  6
+ * Arcana    7
+ * Ancient   8
+ */
+pub fn rarity_class(rarity_sum: u16) -> u8 {
+    match rarity_sum {
+        0..=15 => 0,  //TODO check it? "id":"2490" for example
+        16..=23 => 1, // Uncommon
+        24..=31 => 2, // Rare
+        32..=39 => 3, // Mythical
+        40..=47 => 4, // Legendary
+        48..=55 => 5, // Imortal
+        56..=63 => 6, // Arcana
+        _ => 7,       // Ancient
+    }
+}
+// "id":"2851"
+// 5271532761388019919425566412768461699999999998899999999999988999999999999996
+//  "id":"1894"
+//  17213176947417029247062885245301688801479160274101322991103071845030308089925
+// ... attack (20 digits) defence (20 digits) trailing (2 digits)
+pub fn decode_combat(gen_battle: &str) -> Option<CombatGenes> {
+    let len = gen_battle.len();
+    if len < GEN_BATTLE_LEN {
+        return None;
+    }
+    let gens = gen_battle.get(len - GEN_BATTLE_LEN..)?;
+    if !gens.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let num = |a: usize| gens[a..a + 2].parse::<u8>().unwrap_or(0);
+    let mut combat = CombatGenes {
+        attack: [0; COMBAT_SLOTS],
+        defence: [0; COMBAT_SLOTS],
+        attack_total: 0,
+        defence_total: 0,
+        strength: 0,
+        trailing: num(GEN_BATTLE_LEN - 2),
+    };
+    for slot in 0..COMBAT_SLOTS {
+        combat.attack[slot] = num(slot * 2);
+        combat.defence[slot] = num(COMBAT_SLOTS * 2 + slot * 2);
+    }
+    combat.attack_total = combat.attack.iter().map(|x| u16::from(*x)).sum();
+    combat.defence_total = combat.defence.iter().map(|x| u16::from(*x)).sum();
+    combat.strength = combat.attack_total + combat.defence_total;
+    Some(combat)
+}
@@ -0,0 +1,4 @@
+pub mod structs;
+pub use structs::*;
+pub mod decoder;
+pub use decoder::*;
@@ -0,0 +1,115 @@
+// "777" and the 13 sections of an image with every digit zero
+pub const EMPTY_GEN_IMAGE: &str = "77700000000000000000000000000";
+pub const GEN_IMAGE_LEN: usize = 29;
+// ten attack and ten defence slots of two digits and two trailing digits,
+// anything before them is not used
+pub const GEN_BATTLE_LEN: usize = 42;
+pub const COMBAT_SLOTS: usize = 10;
+pub const EMPTY_GEN_BATTLE: &str = "000000000000000000000000000000000000000000";
+
+// One section of gen_image. Color is there for the two digit sections,
+// rarity for the ones counted by the rarity of the dragon.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct Gene {
+    #[serde(rename = "type")]
+    pub kind: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rarity: Option<u8>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ImageGenes {
+    pub aura: Gene,
+    pub horns: Gene,
+    pub scales: Gene,
+    pub spots: Gene,
+    pub tail: Gene,
+    pub wings: Gene,
+    pub spins: Gene,
+    pub body: Gene,
+    pub eyes: Gene,
+    pub head: Gene,
+    pub claws: Gene,
+    pub color_scheme: Gene,
+    pub mutagen: Gene,
+    pub rarity_sum: u16,
+    pub rarity: u8,
+}
+
+// the first COLORED_SECTIONS have a color digit
+pub const COLORED_SECTIONS: usize = 9;
+pub const SECTION_NAMES: [&str; 13] = [
+    "aura",
+    "horns",
+    "scales",
+    "spots",
+    "tail",
+    "wings",
+    "spins",
+    "body",
+    "eyes",
+    "head",
+    "claws",
+    "color_scheme",
+    "mutagen",
+];
+
+// Slots from the left (most significant) to the right of gen_battle.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct CombatGenes {
+    pub attack: [u8; COMBAT_SLOTS],
+    pub defence: [u8; COMBAT_SLOTS],
+    pub attack_total: u16,
+    pub defence_total: u16,
+    pub strength: u16,
+    pub trailing: u8,
+}
+
+impl ImageGenes {
+    // (name, gene) of the 13 sections in gen_image order
+    pub fn sections(&self) -> [(&'static str, &Gene); 13] {
+        let genes = [
+            &self.aura,
+            &self.horns,
+            &self.scales,
+            &self.spots,
+            &self.tail,
+            &self.wings,
+            &self.spins,
+            &self.body,
+            &self.eyes,
+            &self.head,
+            &self.claws,
+            &self.color_scheme,
+            &self.mutagen,
+        ];
+        std::array::from_fn(|i| (SECTION_NAMES[i], genes[i]))
+    }
+}
+
+// https://github.com/DeepDragons/dragon-zil/blob/master/src/mixins/utils.js#L372
+pub const RI: RarityConst = RarityConst {
+    aura: [0, 2, 3, 3, 4, 5],
+    horns: [0, 2, 3, 3, 3, 3, 4, 5],
+    scales: [1, 2, 3, 4, 5],
+    spots: [0, 1, 2, 2, 2, 2, 2, 2, 8, 5],
+    tail: [0, 2, 3, 3, 3, 3, 3, 4, 5],
+    wings: [0, 1, 2, 3, 4, 5],
+    body: [0, 1, 4, 6],
+    eyes: [0, 1, 3, 3, 3, 3, 4, 4, 5, 6],
+    head: [0, 1, 3, 5, 6, 7],
+};
+
+pub struct RarityConst {
+    pub aura: [u8; 6],
+    pub horns: [u8; 8],
+    pub scales: [u8; 5],
+    pub spots: [u8; 10],
+    pub tail: [u8; 9],
+    pub wings: [u8; 6],
+    pub body: [u8; 4],
+    pub eyes: [u8; 10],
+    pub head: [u8; 6],
+}
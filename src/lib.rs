@@ -0,0 +1,15 @@
+extern crate reqwest;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+pub mod address;
+pub mod archive;
+pub mod config;
+pub mod events;
+pub mod genes;
+pub mod sales;
+pub mod state;
+pub mod web_api;
+pub mod webhooks;
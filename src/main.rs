@@ -1,52 +1,50 @@
-extern crate reqwest;
-extern crate serde;
-#[macro_use]
-extern crate serde_derive;
-extern crate serde_json;
-
-mod web_api;
-use web_api::{
-    routes::get_dragon_by_id, routes::get_dragons, routes::get_from_battle, routes::get_from_breed,
-    routes::get_from_market,
-};
-mod state;
-use state::reciver::{create, get_block_num, update_state};
-use std::sync::{Arc, Mutex};
-use tide::http::headers::HeaderValue;
-
-pub const DEFAULT_API_URL: &str = "127.0.0.1:8083";
+use dragon_api::archive::{self, Archive};
+use dragon_api::config;
+use dragon_api::sales;
+use dragon_api::state::reciver::update_state;
+use dragon_api::state::{snapshot, AppState, Shared};
+use dragon_api::web_api::build_server;
+use dragon_api::webhooks;
+use std::sync::Arc;
 
 #[tokio::main]
 async fn main() -> tide::Result<()> {
     dotenv::dotenv().ok();
 
-    let app_state = Arc::new(Mutex::new(Box::new(create().await)));
-    let start_block_num = get_block_num().await;
-    let state_ref = Arc::clone(&app_state);
+    let config = config::load()?;
+    // serve the last snapshot (or nothing) while the refresher catches up
+    let (start_block_num, app_state) = match snapshot::load(&config.snapshot_path) {
+        Ok(Some(result)) => result,
+        Ok(None) => (0, AppState::default()),
+        Err(e) => {
+            eprintln!("Cannot load snapshot {}: {}", config.snapshot_path, e);
+            (0, AppState::default())
+        }
+    };
+    let webhooks = webhooks::load(&config.webhooks_path).unwrap_or_else(|e| {
+        eprintln!("Cannot load webhooks {}: {}", config.webhooks_path, e);
+        Default::default()
+    });
+    let api_url = config.api_url.clone();
+    let shared = Shared::new(config, app_state);
+    *shared.webhooks.lock().unwrap() = webhooks;
+    match sales::load(&shared.config.sales_path) {
+        Ok(sales) => *shared.sales.write().unwrap() = sales,
+        Err(e) => eprintln!("Cannot load sales {}: {}", shared.config.sales_path, e),
+    }
+    match archive::load(&shared.config.archive_path) {
+        Ok(checkpoints) => *archive::lock(&shared) = Archive::new(checkpoints),
+        Err(e) => eprintln!("Cannot load archive {}: {}", shared.config.archive_path, e),
+    }
+    // subscribed before the first refresh, nothing is missed
+    let events = shared.events.subscribe();
+    tokio::spawn(webhooks::run(Arc::clone(&shared), events));
+    let state_ref = Arc::clone(&shared);
     tokio::spawn(async move {
-        update_state(start_block_num, state_ref).await;
+        update_state(state_ref, start_block_num).await;
     });
-    let api_url = match std::env::var("API_URL") {
-        Ok(val) => val,
-        Err(_) => String::from(DEFAULT_API_URL),
-    };
     println!("Dragons backend is starting on {}", api_url);
-    let mut app = tide::with_state(app_state);
-    #[cfg(debug_assertions)]
-    {
-        println!("Debug mode, CORS allow \"*\"");
-        let cors_debug = tide::security::CorsMiddleware::new()
-            .allow_methods("GET".parse::<HeaderValue>().unwrap())
-            .allow_origin(tide::security::Origin::from("*"))
-            .allow_credentials(false);
-        app.with(cors_debug);
-    }
-    app.at("/api/v1/dragons").get(get_dragons);
-    app.at("/api/v1/dragons/:id").get(get_dragon_by_id);
-    app.at("/api/v1/market").get(get_from_market);
-    app.at("/api/v1/battle").get(get_from_battle);
-    app.at("/api/v1/breed").get(get_from_breed);
-
+    let app = build_server(shared);
     app.listen(api_url).await?;
 
     Ok(())
@@ -0,0 +1,46 @@
+use crate::sales::{Candle, Sale, Volume};
+use std::collections::BTreeMap;
+
+// sales made at `since` or later
+pub fn volume(sales: &[Sale], since: u64) -> Volume {
+    let (mut total, mut trades) = (0u128, 0);
+    for price in sales.iter().filter(|x| x.time >= since).filter_map(price) {
+        total = total.saturating_add(price);
+        trades += 1;
+    }
+    Volume {
+        volume: total.to_string(),
+        trades,
+    }
+}
+
+// Candles of the intervals since `since` that have sales, oldest first.
+// Intervals are aligned to the unix epoch, e.g. days start at 00:00 UTC.
+pub fn candles(sales: &[Sale], interval: u64, since: u64) -> Vec<Candle> {
+    let mut buckets: BTreeMap<u64, Vec<u128>> = BTreeMap::new();
+    for sale in sales.iter().filter(|x| x.time >= since) {
+        if let Some(price) = price(sale) {
+            let start = sale.time - sale.time % interval;
+            buckets.entry(start).or_default().push(price);
+        }
+    }
+    buckets
+        .into_iter()
+        .map(|(start, prices)| Candle {
+            start,
+            open: prices[0].to_string(),
+            high: prices.iter().max().unwrap_or(&0).to_string(),
+            low: prices.iter().min().unwrap_or(&0).to_string(),
+            close: prices[prices.len() - 1].to_string(),
+            volume: prices
+                .iter()
+                .fold(0u128, |sum, x| sum.saturating_add(*x))
+                .to_string(),
+            trades: prices.len(),
+        })
+        .collect()
+}
+
+fn price(sale: &Sale) -> Option<u128> {
+    sale.price.parse().ok()
+}
@@ -0,0 +1,59 @@
+use crate::events::{ChangeEvent, EventKind, ListKind};
+use crate::sales::{append, Sale};
+use crate::state::{AppState, Shared};
+use std::collections::HashMap;
+
+// A market listing that is gone while the dragon changed hands is a sale at
+// the listed price. An order cancelled and a transfer made in the same
+// refresh look the same, they are rare enough. The events are the ones of
+// the refresh to new, block and time the refresh's: the node isn't asked
+// for the transaction of each sale.
+pub fn detect(events: &[ChangeEvent], new: &AppState, block: u128, time: u64) -> Vec<Sale> {
+    let mut delisted: HashMap<&str, &str> = HashMap::new();
+    let mut transfers = Vec::new();
+    for event in events {
+        match (&event.kind, &event.list, &event.from, &event.to) {
+            (EventKind::Delisted, Some(ListKind::Market), Some(price), _) => {
+                delisted.insert(&event.id, price);
+            }
+            (EventKind::Transfer, _, Some(seller), Some(buyer)) => {
+                transfers.push((&event.id, seller, buyer));
+            }
+            _ => {}
+        }
+    }
+    transfers
+        .into_iter()
+        .filter_map(|(id, seller, buyer)| {
+            let price = delisted.remove(id.as_str())?;
+            Some(Sale {
+                rarity: new.all_id_rarity.get(id).cloned().unwrap_or_default(),
+                stage: new
+                    .main_state
+                    .token_stage
+                    .get(id)
+                    .and_then(|x| x.parse().ok())
+                    .unwrap_or_default(),
+                id: id.clone(),
+                price: price.to_string(),
+                seller: seller.clone(),
+                buyer: buyer.clone(),
+                block,
+                time,
+            })
+        })
+        .collect()
+}
+
+// Appends to the store first, a sale is served only once it is saved.
+pub fn record(shared: &Shared, sales: Vec<Sale>) {
+    let path = &shared.config.sales_path;
+    if let Err(e) = append(path, &sales) {
+        eprintln!("Cannot save sales {}: {}", path, e);
+        return;
+    }
+    match shared.sales.write() {
+        Ok(mut all) => all.extend(sales),
+        Err(poisoned) => poisoned.into_inner().extend(sales),
+    }
+}
@@ -0,0 +1,8 @@
+pub mod structs;
+pub use structs::*;
+pub mod store;
+pub use store::{append, load};
+pub mod detect;
+pub use detect::{detect, record};
+pub mod analytics;
+pub use analytics::{candles, volume};
@@ -0,0 +1,37 @@
+use crate::sales::Sale;
+use std::fs::{self, OpenOptions};
+use std::io::{Error, ErrorKind, Write};
+use std::path::Path;
+
+// One JSON sale per line, only ever appended to.
+pub fn append(path: &str, sales: &[Sale]) -> Result<(), Error> {
+    if path.is_empty() || sales.is_empty() {
+        return Ok(());
+    }
+    let mut text = String::new();
+    for sale in sales {
+        text.push_str(&serde_json::to_string(sale).map_err(Error::other)?);
+        text.push('\n');
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    file.write_all(text.as_bytes())
+}
+
+// All the sales in the order they were made, none if there is no file yet.
+// A torn last line (a crash while appending) is skipped.
+pub fn load(path: &str) -> Result<Vec<Sale>, Error> {
+    if path.is_empty() || !Path::new(path).exists() {
+        return Ok(Vec::new());
+    }
+    let text = fs::read_to_string(path)?;
+    let lines: Vec<&str> = text.lines().filter(|x| !x.trim().is_empty()).collect();
+    let mut sales = Vec::with_capacity(lines.len());
+    for (i, line) in lines.iter().enumerate() {
+        match serde_json::from_str(line) {
+            Ok(sale) => sales.push(sale),
+            Err(_) if i + 1 == lines.len() => break,
+            Err(e) => return Err(Error::new(ErrorKind::InvalidData, e)),
+        }
+    }
+    Ok(sales)
+}
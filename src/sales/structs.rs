@@ -0,0 +1,31 @@
+// A filled market order. Rarity and stage are the ones at the time of the sale.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct Sale {
+    pub id: String,
+    pub price: String,
+    pub seller: String,
+    pub buyer: String,
+    pub block: u128, // the refresh that noticed it, not the exact tx block
+    pub time: u64,   // unix seconds, same
+    pub rarity: u8,
+    pub stage: u8,
+}
+
+// Sum of the prices and count of the sales of a period.
+#[derive(Serialize, Clone, PartialEq, Eq, Debug, Default)]
+pub struct Volume {
+    pub volume: String,
+    pub trades: usize,
+}
+
+// Prices of the sales in [start, start + interval), in the order they were made.
+#[derive(Serialize, Clone, PartialEq, Eq, Debug)]
+pub struct Candle {
+    pub start: u64, // unix seconds
+    pub open: String,
+    pub high: String,
+    pub low: String,
+    pub close: String,
+    pub volume: String,
+    pub trades: usize,
+}
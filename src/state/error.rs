@@ -0,0 +1,23 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum StateError {
+    // node is unreachable, answers non 200 or with a JSON-RPC error
+    Transport(String),
+    // response body doesn't match the expected state (what, serde error)
+    Decode(&'static str, serde_json::Error),
+    // contract states don't agree with each other
+    Inconsistent(String),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Transport(e) => write!(f, "transport error: {}", e),
+            StateError::Decode(what, e) => write!(f, "cannot decode {}: {}", what, e),
+            StateError::Inconsistent(e) => write!(f, "inconsistent state: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StateError {}
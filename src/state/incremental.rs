@@ -0,0 +1,239 @@
+use crate::config::Config;
+use crate::state::reciver::*;
+use crate::state::*;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+
+// Main contract events of minters and operators (ZRC-1), no token changes
+// and nothing the API serves: they need no patching.
+const NON_TOKEN_EVENTS: [&str; 3] = [
+    "AddMinterSuccess",
+    "RemoveMinterSuccess",
+    "SetApprovalForAllSuccess",
+];
+
+// Contracts touched by the events of a block range.
+#[derive(Default)]
+struct Touched {
+    tokens: BTreeSet<String>,
+    battle: bool,
+    breed: bool,
+    market: bool,
+    names: bool,
+    history: bool,
+}
+
+// Full download when asked to (schedule), when incremental mode is off or there
+// is nothing to patch yet, and when patching fails. The flag tells if it was full.
+pub async fn next_state(
+    config: &Config,
+    current: &AppState,
+    from: u128,
+    to: u128,
+    full: bool,
+) -> Result<(AppState, bool), StateError> {
+    if !full && config.incremental && !current.all_id_list.is_empty() {
+        match refresh(config, current, from, to).await {
+            Ok(state) => return Ok((state, false)),
+            Err(e) => eprintln!(
+                "Incremental refresh of blocks {}..{} failed: {}",
+                from, to, e
+            ),
+        }
+    }
+    let state = create(config, current.history()).await?;
+    Ok((state, true))
+}
+
+// Applies the events of blocks (from, to] to the current state:
+// Transfer/Mint/Burn... of the main contract re-read the entries of the tokens
+// they name, events of FightPlace, BreedPlace, MarketPlace and the name
+// contract re-read their (small) waiting lists, orderbook and names.
+pub async fn refresh(
+    config: &Config,
+    current: &AppState,
+    from: u128,
+    to: u128,
+) -> Result<AppState, StateError> {
+    let blocks = to.saturating_sub(from);
+    if blocks > config.max_incremental_blocks as u128 {
+        return Err(StateError::Inconsistent(format!(
+            "{} blocks behind, over max_incremental_blocks",
+            blocks
+        )));
+    }
+    let mut touched = Touched::default();
+    for block in from + 1..=to {
+        for event in fetch_block_events(config, block).await? {
+            collect_event(config, &event, &mut touched)?;
+        }
+    }
+    let mut states = current.contract_states();
+    if !touched.tokens.is_empty() {
+        for id in &touched.tokens {
+            patch_token(config, &mut states.main, id).await?;
+        }
+        patch_supply(config, &mut states.main).await?;
+    }
+    if touched.battle {
+        (states.battle, states.wounds) = fetch_battle(config).await?;
+    }
+    if touched.breed {
+        states.breed = fetch_breed(config).await?;
+    }
+    if touched.market {
+        states.orderbook = fetch_orderbook(config).await?;
+    }
+    if touched.names {
+        states.names = fetch_names(config).await?;
+    }
+    let history = if touched.history {
+        update_history(config, &current.history()).await?
+    } else {
+        current.history()
+    };
+    build_state(states, history)
+}
+
+// events of the successful transactions of a block
+async fn fetch_block_events(config: &Config, block: u128) -> Result<Vec<TxEvent>, StateError> {
+    let (url, retries) = (&config.rpc_url, config.rpc_retries);
+    let body = rpc_body(GETTXBLOCK, json!([block.to_string()]));
+    let text = do_request(&body, url, retries).await?;
+    let block_resp: Resp<TxBlock> = decode(&text, "tx block")?;
+    if block_resp.result.header.num_txns == 0 {
+        return Ok(Vec::new());
+    }
+    let body = rpc_body(GETTXBODIES, json!([block.to_string()]));
+    let text = do_request(&body, url, retries).await?;
+    let bodies_resp: Resp<Vec<TxBody>> = decode(&text, "tx bodies")?;
+    Ok(bodies_resp
+        .result
+        .into_iter()
+        .filter(|tx| tx.receipt.success)
+        .flat_map(|tx| tx.receipt.event_logs)
+        .collect())
+}
+
+fn collect_event(
+    config: &Config,
+    event: &TxEvent,
+    touched: &mut Touched,
+) -> Result<(), StateError> {
+    let contracts = &config.contracts;
+    let from = |addr: &str| rpc_address(addr).eq_ignore_ascii_case(rpc_address(&event.address));
+    if from(&contracts.main) {
+        if NON_TOKEN_EVENTS.contains(&event._eventname.as_str()) {
+            return Ok(());
+        }
+        let mut ids = BTreeSet::new();
+        for param in event.params.iter().filter(|p| is_token_param(&p.vname)) {
+            token_ids(&param.value, &mut ids);
+        }
+        // can't tell what changed, e.g. an event of a newer contract version
+        if ids.is_empty() {
+            return Err(StateError::Inconsistent(format!(
+                "{} event of the main contract without a token id",
+                event._eventname
+            )));
+        }
+        touched.tokens.extend(ids);
+    } else if from(&contracts.battle) {
+        touched.battle = true;
+    } else if from(&contracts.fight) {
+        // the loser gets wounded
+        touched.battle = true;
+        touched.history = true;
+    } else if from(&contracts.breed) {
+        touched.breed = true;
+        touched.history = true;
+    } else if from(&contracts.market) {
+        touched.market = true;
+    } else if from(&contracts.name) {
+        touched.names = true;
+    }
+    Ok(())
+}
+fn is_token_param(vname: &str) -> bool {
+    vname == "id" || vname.ends_with("_id") || vname.ends_with("_ids")
+}
+// Uint256 values and lists of them
+fn token_ids(value: &Value, ids: &mut BTreeSet<String>) {
+    match value {
+        Value::String(s) if !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit()) => {
+            ids.insert(s.clone());
+        }
+        Value::Array(items) => items.iter().for_each(|item| token_ids(item, ids)),
+        _ => {}
+    }
+}
+
+// Re-reads every map entry of a token, a missing owner means it was burned.
+async fn patch_token(config: &Config, main: &mut MainState, id: &str) -> Result<(), StateError> {
+    let owner: Option<String> = fetch_entry(config, "token_owners", id).await?;
+    let mut owners: Vec<String> = main.token_owners.get(id).into_iter().cloned().collect();
+    let fields = [
+        ("token_owners", &mut main.token_owners),
+        ("token_stage", &mut main.token_stage),
+        ("token_gen_image", &mut main.token_gen_image),
+        ("token_gen_battle", &mut main.token_gen_battle),
+        ("token_uris", &mut main.token_uris),
+        ("token_approvals", &mut main.token_approvals),
+    ];
+    for (field, map) in fields {
+        let value = match (&owner, field) {
+            (None, _) => None,
+            (Some(owner), "token_owners") => Some(owner.clone()),
+            _ => fetch_entry(config, field, id).await?,
+        };
+        set_entry(map, id, value);
+    }
+    owners.extend(owner);
+    owners.dedup();
+    for owner in &owners {
+        let count = fetch_entry(config, "owned_token_count", owner).await?;
+        set_entry(&mut main.owned_token_count, owner, count);
+        let stages = fetch_entry(config, "tokens_owner_stage", owner).await?;
+        set_entry(&mut main.tokens_owner_stage, owner, stages);
+    }
+    Ok(())
+}
+async fn patch_supply(config: &Config, main: &mut MainState) -> Result<(), StateError> {
+    for (field, value) in [
+        ("total_supply", &mut main.total_supply),
+        ("token_id_count", &mut main.token_id_count),
+    ] {
+        let body = sub_state_key_body(&config.contracts.main, field, &[]);
+        let text = do_request(&body, &config.rpc_url, config.rpc_retries).await?;
+        let mut resp: Resp<HMStrings> = decode(&text, "main state")?;
+        *value = resp.result.remove(field).unwrap_or_default();
+    }
+    // a missed Mint or Burn shows up here
+    if main.total_supply.parse::<usize>().ok() != Some(main.token_owners.len()) {
+        return Err(StateError::Inconsistent(format!(
+            "total_supply {} but {} token owners",
+            main.total_supply,
+            main.token_owners.len()
+        )));
+    }
+    Ok(())
+}
+// entry `key` of the main contract map `field`, None when it is not there
+async fn fetch_entry<T>(config: &Config, field: &str, key: &str) -> Result<Option<T>, StateError>
+where
+    T: for<'a> serde::Deserialize<'a>,
+{
+    let body = sub_state_key_body(&config.contracts.main, field, &[key]);
+    let text = do_request(&body, &config.rpc_url, config.rpc_retries).await?;
+    let resp: Resp<Option<HashMap<String, HashMap<String, T>>>> = decode(&text, "main state")?;
+    Ok(resp
+        .result
+        .and_then(|mut fields| fields.remove(field))
+        .and_then(|mut entries| entries.remove(key)))
+}
+fn set_entry<T>(map: &mut HashMap<String, T>, key: &str, value: Option<T>) {
+    match value {
+        Some(value) => map.insert(key.to_string(), value),
+        None => map.remove(key),
+    };
+}
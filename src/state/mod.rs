@@ -1,4 +1,8 @@
 pub mod structs;
 pub use structs::*;
+pub mod error;
+pub use error::StateError;
+pub mod incremental;
 pub mod reciver;
-pub use reciver::create;
+pub mod snapshot;
+pub use reciver::{create, get_block_num};
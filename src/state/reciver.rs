@@ -1,73 +1,201 @@
+use crate::address::canonical;
+use crate::archive;
+use crate::config::Config;
+use crate::genes::{decode_combat, decode_image, EMPTY_GEN_BATTLE, EMPTY_GEN_IMAGE};
+use crate::sales;
+use crate::state::incremental::next_state;
 use crate::state::*;
 use reqwest::StatusCode;
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
-use tokio::time::{sleep, Duration};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::task::spawn_blocking;
+use tokio::time::{sleep, Duration, Instant};
 
-async fn do_request(body: &'static str, url: &'static str) -> String {
+// Delay before the next attempt/refresh after `failures` failures in a row.
+fn backoff(failures: u32) -> Duration {
+    Duration::from_secs(std::cmp::min(MAX_BACKOFF, 1 << std::cmp::min(failures, 16)))
+}
+pub(crate) async fn do_request(body: &str, url: &str, retries: u32) -> Result<String, StateError> {
     let client = reqwest::Client::new();
-    let mut text: String;
-    let mut delay = 0;
-    loop {
-        sleep(Duration::from_secs(delay)).await;
-        delay = (delay + 1) % 15;
+    let mut last_error = String::new();
+    for attempt in 0..std::cmp::max(retries, 1) {
+        if attempt > 0 {
+            sleep(backoff(attempt - 1)).await;
+        }
         let response = match client
             .post(url)
             .header("content-type", "application/json")
-            .body(body)
+            .body(body.to_string())
             .send()
             .await
         {
             Ok(result) => result,
-            Err(_) => continue,
+            Err(e) => {
+                last_error = e.to_string();
+                continue;
+            }
         };
-        if response.status() == StatusCode::OK {
-            text = match response.text().await {
-                Ok(result) => result,
-                Err(_) => continue,
-            };
-            // check for node bug "Address does not exist"
-            if text[0..8] == *"{\"error\"" {
+        if response.status() != StatusCode::OK {
+            last_error = format!("{} answered {}", url, response.status());
+            continue;
+        }
+        let text = match response.text().await {
+            Ok(result) => result,
+            Err(e) => {
+                last_error = e.to_string();
                 continue;
             }
-            break;
+        };
+        // check for node bug "Address does not exist"
+        if text.starts_with("{\"error\"") {
+            last_error = text;
+            continue;
         }
+        return Ok(text);
     }
-    // TODO remove debug println and text var...
-    if text.len() < 3000 {
-        println!("{}", text);
-    }
-    text
+    Err(StateError::Transport(last_error))
+}
+pub(crate) fn decode<'a, T: serde::Deserialize<'a>>(
+    text: &'a str,
+    what: &'static str,
+) -> Result<T, StateError> {
+    serde_json::from_str(text).map_err(|e| StateError::Decode(what, e))
+}
+// https://dev.zilliqa.com/api/introduction/api-introduction
+pub(crate) fn rpc_body(method: &str, params: serde_json::Value) -> String {
+    json!({"id": "1", "jsonrpc": "2.0", "method": method, "params": params}).to_string()
+}
+// RPC expects the address without 0x, Apollo expects the lowercase 0x form.
+pub(crate) fn rpc_address(addr: &str) -> &str {
+    addr.trim_start_matches("0x")
+}
+fn apollo_address(addr: &str) -> String {
+    format!("0x{}", rpc_address(addr).to_lowercase())
+}
+fn state_body(addr: &str) -> String {
+    rpc_body(GETSTATE, json!([rpc_address(addr)]))
+}
+fn sub_state_body(addr: &str, field: &str) -> String {
+    rpc_body(GETSUBSTATE, json!([rpc_address(addr), field, []]))
+}
+// only the entry of the map field under the given keys
+pub(crate) fn sub_state_key_body(addr: &str, field: &str, keys: &[&str]) -> String {
+    rpc_body(GETSUBSTATE, json!([rpc_address(addr), field, keys]))
+}
+fn events_body(addr: &str, event: &str, page: u64, per_page: u64) -> String {
+    json!({
+        "operationName": "Events",
+        "variables": {
+            "contractAddr": apollo_address(addr),
+            "eventName": event,
+            "page": page,
+            "perPage": per_page,
+        },
+        "query": EVENTS_QUERY,
+    })
+    .to_string()
+}
+pub async fn create(config: &Config, history: EventHistory) -> Result<AppState, StateError> {
+    let states = fetch_states(config).await?;
+    let history = update_history(config, &history).await?;
+    build_state(states, history)
+}
+pub async fn fetch_states(config: &Config) -> Result<ContractStates, StateError> {
+    let names = fetch_names(config).await?;
+    let breed = fetch_breed(config).await?;
+    let orderbook = fetch_orderbook(config).await?;
+    let main = fetch_main(config).await?;
+    let (battle, wounds) = fetch_battle(config).await?;
+    Ok(ContractStates {
+        main,
+        battle,
+        wounds,
+        breed,
+        orderbook,
+        names,
+    })
+}
+pub async fn fetch_main(config: &Config) -> Result<MainState, StateError> {
+    let body = state_body(&config.contracts.main);
+    let text = do_request(&body, &config.rpc_url, config.rpc_retries).await?;
+    let main_resp: Resp<MainState> = decode(&text, "main state")?;
+    Ok(main_resp.result)
+}
+pub async fn fetch_names(config: &Config) -> Result<HMStrings, StateError> {
+    let body = sub_state_body(&config.contracts.name, "dragons_name");
+    let text = do_request(&body, &config.rpc_url, config.rpc_retries).await?;
+    let name_resp: Resp<NameState> = decode(&text, "name state")?;
+    Ok(name_resp.result.dragons_name)
 }
-pub async fn create() -> AppState {
-    let text = do_request(NAMESTATE, URL).await;
-    let name_resp: Resp<NameState> = serde_json::from_str(&text).expect("name state");
-    let text = do_request(BREEDSTATE, URL).await;
-    let breed_resp: Resp<WaitState<BreedItem>> = serde_json::from_str(&text).expect("breed state");
-    let mut breed_id_list: Vec<String> = breed_resp.result.waiting_list.keys().cloned().collect();
+pub async fn fetch_breed(config: &Config) -> Result<HashMap<String, BreedItem>, StateError> {
+    let body = sub_state_body(&config.contracts.breed, "waiting_list");
+    let text = do_request(&body, &config.rpc_url, config.rpc_retries).await?;
+    let breed_resp: Resp<WaitState<BreedItem>> = decode(&text, "breed state")?;
+    Ok(breed_resp.result.waiting_list)
+}
+pub async fn fetch_orderbook(config: &Config) -> Result<HashMap<String, MarketItem>, StateError> {
+    let body = sub_state_body(&config.contracts.market, "orderbook");
+    let text = do_request(&body, &config.rpc_url, config.rpc_retries).await?;
+    let market_resp: Resp<OrderState> = decode(&text, "market state")?;
+    Ok(market_resp.result.orderbook)
+}
+// (waiting_list, wounded_list) of FightPlace
+pub async fn fetch_battle(config: &Config) -> Result<(HMStrings, HMVecStrings), StateError> {
+    let (url, retries) = (&config.rpc_url, config.rpc_retries);
+    let body = sub_state_body(&config.contracts.battle, "waiting_list");
+    let text = do_request(&body, url, retries).await?;
+    let battle_resp: Resp<WaitState<String>> = decode(&text, "battle state")?;
+    let body = sub_state_body(&config.contracts.battle, "wounded_list");
+    let text = do_request(&body, url, retries).await?;
+    let wounds_resp: Resp<WoundState> = decode(&text, "wounds state")?;
+    Ok((
+        battle_resp.result.waiting_list,
+        wounds_resp.result.wounded_list,
+    ))
+}
+pub async fn update_history(
+    config: &Config,
+    history: &EventHistory,
+) -> Result<EventHistory, StateError> {
+    let (fights, fights_cursor) = get_fights_history(config, history).await?;
+    let (parents, breeds_cursor) = get_lineage(config, history).await?;
+    Ok(EventHistory {
+        fights,
+        fights_cursor,
+        parents,
+        breeds_cursor,
+    })
+}
+fn empty_gen(what: &str) -> StateError {
+    StateError::Inconsistent(format!("empty {} is not decoded", what))
+}
+// Derives all the indexes from raw contract states, no requests here.
+pub fn build_state(states: ContractStates, history: EventHistory) -> Result<AppState, StateError> {
+    let mut breed_id_list: Vec<String> = states.breed.keys().cloned().collect();
     let mut breed_owned_id: HashMap<String, Vec<String>> = HashMap::new();
     let mut breed_id_price = HashMap::with_capacity(breed_id_list.len());
-    for (id, breed_item) in breed_resp.result.waiting_list {
+    for (id, breed_item) in &states.breed {
         breed_id_price.insert(id.clone(), breed_item.arguments[0].clone());
-        match breed_owned_id.get_mut(&breed_item.arguments[1]) {
-            Some(x) => x.push(id),
+        let owner = canonical(&breed_item.arguments[1]);
+        match breed_owned_id.get_mut(&owner) {
+            Some(x) => x.push(id.clone()),
             None => {
-                breed_owned_id.insert(breed_item.arguments[1].clone(), vec![id]);
+                breed_owned_id.insert(owner, vec![id.clone()]);
             }
         }
     }
-    let text = do_request(MARKETSTATE, URL).await;
-    let market_resp: Resp<OrderState> = serde_json::from_str(&text).expect("market state");
-    let market_len = market_resp.result.orderbook.len();
+    let market_len = states.orderbook.len();
     let mut market_id_price: HashMap<String, String> = HashMap::with_capacity(market_len);
     let mut market_id_order: HashMap<String, String> = HashMap::with_capacity(market_len);
     let mut market_id_list: Vec<String> = Vec::with_capacity(market_len);
     let mut market_owned_id: HashMap<String, Vec<String>> = HashMap::new();
-    // TODO Add with_capacity
-    let mut all_id_owner = HashMap::new();
-    for i in market_resp.result.orderbook.into_values() {
+    let main_state = states.main;
+    let mut all_id_owner = HashMap::with_capacity(main_state.token_owners.len());
+    for i in states.orderbook.values() {
         let (owner, price, id, order_id) = (
-            i.arguments[0].clone(),
+            canonical(&i.arguments[0]),
             i.arguments[1].clone(),
             i.arguments[2].clone(),
             i.arguments[3].clone(),
@@ -83,132 +211,331 @@ pub async fn create() -> AppState {
             }
         };
     }
-    let text = do_request(MAINSTATE, URL).await;
-    // TODO error handling
-    let main_resp: Resp<MainState> = serde_json::from_str(&text).expect("main state");
-    let text = do_request(BATTLESTATE, URL).await;
-    // TODO error handling
-    let battle_resp: Resp<WaitState<String>> = serde_json::from_str(&text).expect("battle state");
-    drop(text);
-    let mut battle_id_list: Vec<String> = battle_resp.result.waiting_list.keys().cloned().collect();
+    let mut battle_id_list: Vec<String> = states.battle.keys().cloned().collect();
     let mut battle_owned_id: HashMap<String, Vec<String>> = HashMap::new();
-    for id in battle_resp.result.waiting_list.keys() {
-        // TODO Error handling
-        let owner = main_resp.result.token_owners.get(id).unwrap();
-        match battle_owned_id.get_mut(owner) {
+    for id in states.battle.keys() {
+        let owner = main_state
+            .token_owners
+            .get(id)
+            .ok_or_else(|| StateError::Inconsistent(format!("battle id {} has no owner", id)))?;
+        let owner = canonical(owner);
+        match battle_owned_id.get_mut(&owner) {
             Some(x) => x.push(id.to_string()),
             None => {
-                battle_owned_id.insert(owner.to_string(), vec![id.to_string()]);
+                battle_owned_id.insert(owner, vec![id.to_string()]);
             }
         }
     }
-    let text = do_request(WOUNDSTATE, URL).await;
-    let wounds_resp: Resp<WoundState> = serde_json::from_str(&text).expect("wounds state");
-    drop(text);
-    for (id, owner) in &main_resp.result.token_owners {
-        match all_id_owner.get(id) {
-            None => {
-                all_id_owner.insert(id.to_string(), owner.to_string());
-            }
-            Some(_) => {}
+    for (id, owner) in &main_state.token_owners {
+        if !all_id_owner.contains_key(id) {
+            all_id_owner.insert(id.to_string(), canonical(owner));
         }
     }
-    // TODO error handling
     let parse_cmp = |a: &String, b: &String| {
         a.parse::<u128>()
             .unwrap_or(u128::MAX)
             .cmp(&b.parse::<u128>().unwrap_or(u128::MAX))
     };
-    let mut all_owned_id = HashMap::with_capacity(main_resp.result.tokens_owner_stage.len());
-    for (key, val) in &main_resp.result.tokens_owner_stage {
+    let mut all_owned_id = HashMap::with_capacity(main_state.tokens_owner_stage.len());
+    for (key, val) in &main_state.tokens_owner_stage {
+        let key = canonical(key);
         let mut tokens: Vec<String> = val.keys().cloned().collect();
-        if let Some(x) = market_owned_id.get_mut(key) {
+        if let Some(x) = market_owned_id.get_mut(&key) {
             tokens.extend_from_slice(x);
         }
         tokens.sort_unstable_by(parse_cmp);
-        all_owned_id.insert(key.to_string(), tokens);
+        all_owned_id.insert(key, tokens);
     }
-    let all_len = main_resp.result.token_stage.len();
+    let all_len = main_state.token_stage.len();
+    let mut all_id_children = collect_children(&history.parents);
     let mut all_id_rarity: HashMap<String, u8> = HashMap::with_capacity(all_len);
     let mut all_id_strength: HashMap<String, u16> = HashMap::with_capacity(all_len);
+    let mut all_id_combat: HashMap<String, (u16, u16)> = HashMap::with_capacity(all_len);
     let mut all_id_list: Vec<String> = Vec::with_capacity(all_len);
-    for str_id in main_resp.result.token_stage.keys() {
+    for str_id in main_state.token_stage.keys() {
+        let gen_image = main_state
+            .token_gen_image
+            .get(str_id)
+            .map_or(EMPTY_GEN_IMAGE, |x| x);
+        let gen_battle = main_state
+            .token_gen_battle
+            .get(str_id)
+            .map_or(EMPTY_GEN_BATTLE, |x| x);
+        // a malformed gen is taken as the empty one, the refresh goes on
+        let image_genes = match decode_image(gen_image) {
+            Some(genes) => genes,
+            None => {
+                eprintln!("Id {} has malformed gen_image {}", str_id, gen_image);
+                decode_image(EMPTY_GEN_IMAGE).ok_or_else(|| empty_gen("gen_image"))?
+            }
+        };
+        let combat_genes = match decode_combat(gen_battle) {
+            Some(genes) => genes,
+            None => {
+                eprintln!("Id {} has malformed gen_battle {}", str_id, gen_battle);
+                decode_combat(EMPTY_GEN_BATTLE).ok_or_else(|| empty_gen("gen_battle"))?
+            }
+        };
         all_id_list.push(str_id.to_string());
-        all_id_rarity.insert(
-            str_id.to_string(),
-            calc_rarity(
-                main_resp
-                    .result
-                    .token_gen_image
-                    .get(str_id)
-                    .unwrap_or(&"00000000000000000000000000".to_string()),
-            ),
-        );
-        all_id_strength.insert(
+        all_id_rarity.insert(str_id.to_string(), image_genes.rarity);
+        all_id_strength.insert(str_id.to_string(), combat_genes.strength);
+        all_id_combat.insert(
             str_id.to_string(),
-            calc_strength(
-                main_resp
-                    .result
-                    .token_gen_battle
-                    .get(str_id)
-                    .unwrap_or(&"0".to_string()),
-            ),
+            (combat_genes.attack_total, combat_genes.defence_total),
         );
     }
     all_id_list.sort_unstable_by(parse_cmp);
+    for children in all_id_children.values_mut() {
+        children.sort_unstable_by(parse_cmp);
+    }
     market_id_list.sort_unstable_by(parse_cmp);
+    for owned in [
+        &mut market_owned_id,
+        &mut battle_owned_id,
+        &mut breed_owned_id,
+    ] {
+        for tokens in owned.values_mut() {
+            tokens.sort_unstable_by(parse_cmp);
+        }
+    }
     battle_id_list.sort_unstable_by(parse_cmp);
     breed_id_list.sort_unstable_by(parse_cmp);
-    AppState {
+    let mut name_index: Vec<(String, String)> = states
+        .names
+        .iter()
+        .filter(|(id, _)| main_state.token_stage.contains_key(*id))
+        .map(|(id, name)| (name.to_lowercase(), id.clone()))
+        .collect();
+    name_index.sort_unstable_by(|a, b| a.0.cmp(&b.0).then_with(|| parse_cmp(&a.1, &b.1)));
+    let mut state = AppState {
+        version: 0,
         all_id_list,
         all_owned_id,
         all_id_owner,
         all_id_rarity,
         all_id_strength,
-        all_id_fights: Default::default(),
-        // all_id_fights: get_fights_history(all_len).await,
-        all_id_wounds: wounds_resp.result.wounded_list,
-        main_state: main_resp.result,
+        all_id_combat,
+        all_id_fights: history.fights,
+        fights_cursor: history.fights_cursor,
+        all_id_parents: history.parents,
+        all_id_children,
+        breeds_cursor: history.breeds_cursor,
+        all_id_wounds: states.wounds,
+        main_state,
         battle_id_list,
-        battle_id_price: battle_resp.result.waiting_list,
+        battle_id_price: states.battle,
         battle_owned_id,
+        breed_state: states.breed,
         breed_id_list,
         breed_id_price,
         breed_owned_id,
+        orderbook: states.orderbook,
         market_id_list,
         market_id_price,
         market_id_order,
         market_owned_id,
-        id_name: name_resp.result.dragons_name,
+        id_name: states.names,
+        name_index,
+        stats: Stats::default(),
+    };
+    state.stats = collect_stats(&state)?;
+    Ok(state)
+}
+// Events of txs after the cursor, or None if the cursor isn't in the history anymore.
+async fn fetch_events(
+    config: &Config,
+    addr: &str,
+    event: &str,
+    cursor: &TxCursor,
+) -> Result<Option<(Vec<EventItems>, TxCursor)>, StateError> {
+    let per_page = std::cmp::max(config.apollo_page_size, 1);
+    let mut new_cursor = cursor.clone();
+    let mut events = Vec::new();
+    // start from the last processed tx to check that it is still in place
+    let (mut page, mut skip) = match cursor.processed {
+        0 => (1, 0),
+        x => ((x - 1) / per_page + 1, (x - 1) % per_page),
+    };
+    let mut check = cursor.processed > 0;
+    loop {
+        let body = events_body(addr, event, page, per_page);
+        let text = do_request(&body, &config.apollo_url, config.rpc_retries).await?;
+        let result: Data = decode(&text, "event logs")?;
+        let result = result.data.tx_pagination;
+        if check {
+            match result.items.get(skip as usize) {
+                Some(tx) if tx.id == cursor.last_tx => skip += 1,
+                _ => return Ok(None),
+            }
+            check = false;
+        }
+        for tx in result.items.into_iter().skip(skip as usize) {
+            new_cursor.processed += 1;
+            new_cursor.last_tx = tx.id;
+            for item in tx.receipt.event_logs {
+                if item._eventname == event {
+                    events.push(item);
+                }
+            }
+        }
+        if page >= result.page_info.page_count {
+            break;
+        }
+        page += 1;
+        skip = 0;
     }
+    Ok(Some((events, new_cursor)))
+}
+fn event_param<'a>(event: &'a EventItems, vname: &str) -> Option<&'a str> {
+    event
+        .params
+        .iter()
+        .find(|x| x.vname == vname)
+        .map(|x| x.value.as_str())
 }
-async fn get_fights_history(hmap_size: usize) -> HashMap<String, (u32, u32)> {
-    let text = do_request(GETFIGHT, APPOLO_URL).await;
-    let fight_events: Data = serde_json::from_str(&text).expect("graphql failed");
-    if fight_events.data.txPagination.pageInfo.pageCount > 1 {
-        unimplemented!("YAGNI: all fights > 2147483647, second request needed");
+// New events after the cursor; the flag is set when the whole history was refetched
+// because the cursor is gone, so everything folded from the old events is stale.
+async fn fetch_since(
+    config: &Config,
+    addr: &str,
+    event: &str,
+    cursor: &TxCursor,
+) -> Result<(Vec<EventItems>, TxCursor, bool), StateError> {
+    if let Some((events, cursor)) = fetch_events(config, addr, event, cursor).await? {
+        return Ok((events, cursor, false));
     }
-    let mut fights_history: HashMap<String, (u32, u32)> = HashMap::with_capacity(hmap_size);
-    for item in fight_events.data.txPagination.items {
-        for event in item.receipt.event_logs {
-            if event._eventname == "AfterFightWinLose" {
-                if event.params[0].vname == "token_id_winner" {
-                    add_stats(
-                        &mut fights_history,
-                        &event.params[0].value,
-                        &event.params[1].value,
-                    );
-                } else {
-                    add_stats(
-                        &mut fights_history,
-                        &event.params[1].value,
-                        &event.params[0].value,
-                    );
+    eprintln!("{} history has changed, rebuilding", event);
+    let (events, cursor) = fetch_events(config, addr, event, &TxCursor::default())
+        .await?
+        .ok_or_else(|| StateError::Inconsistent(format!("{} history", event)))?;
+    Ok((events, cursor, true))
+}
+// Applies only the fights after history.fights_cursor.
+async fn get_fights_history(
+    config: &Config,
+    history: &EventHistory,
+) -> Result<(HashMap<String, (u32, u32)>, TxCursor), StateError> {
+    let (events, cursor, rebuilt) = fetch_since(
+        config,
+        &config.contracts.fight,
+        FIGHT_EVENT,
+        &history.fights_cursor,
+    )
+    .await?;
+    let mut fights_history = if rebuilt {
+        HashMap::new()
+    } else {
+        history.fights.clone()
+    };
+    for event in &events {
+        // "looser" as FightPlace spells it
+        let loser = event_param(event, "token_id_looser");
+        match (event_param(event, "token_id_winner"), loser) {
+            (Some(winner), Some(loser)) => add_stats(&mut fights_history, winner, loser),
+            _ => eprintln!(
+                "{} event without a winner or a loser before tx {}, skipped",
+                FIGHT_EVENT, cursor.last_tx
+            ),
+        }
+    }
+    Ok((fights_history, cursor))
+}
+// Applies only the breedings after history.breeds_cursor, (child -> (father, mother)).
+async fn get_lineage(
+    config: &Config,
+    history: &EventHistory,
+) -> Result<(HashMap<String, (String, String)>, TxCursor), StateError> {
+    let (events, cursor, rebuilt) = fetch_since(
+        config,
+        &config.contracts.breed,
+        BREED_EVENT,
+        &history.breeds_cursor,
+    )
+    .await?;
+    let mut parents = if rebuilt {
+        HashMap::new()
+    } else {
+        history.parents.clone()
+    };
+    for event in &events {
+        match (
+            event_param(event, BREED_CHILD),
+            event_param(event, BREED_FATHER),
+            event_param(event, BREED_MOTHER),
+        ) {
+            (Some(child), Some(father), Some(mother)) => {
+                parents.insert(child.to_string(), (father.to_string(), mother.to_string()));
+            }
+            _ => {
+                return Err(StateError::Inconsistent(format!(
+                    "{} event without tokens in tx {}",
+                    BREED_EVENT, cursor.last_tx
+                )))
+            }
+        }
+    }
+    Ok((parents, cursor))
+}
+fn collect_children(parents: &HashMap<String, (String, String)>) -> HashMap<String, Vec<String>> {
+    let mut children: HashMap<String, Vec<String>> = HashMap::new();
+    for (child, (father, mother)) in parents {
+        for parent in [father, mother] {
+            match children.get_mut(parent) {
+                Some(x) => x.push(child.clone()),
+                None => {
+                    children.insert(parent.clone(), vec![child.clone()]);
                 }
             }
         }
     }
-    fights_history
+    children
+}
+fn collect_stats(app_s: &AppState) -> Result<Stats, StateError> {
+    let mut stages = BTreeMap::new();
+    for (id, stage) in &app_s.main_state.token_stage {
+        let stage: u8 = stage
+            .parse()
+            .map_err(|_| StateError::Inconsistent(format!("id {} has stage {}", id, stage)))?;
+        *stages.entry(stage).or_default() += 1;
+    }
+    let mut rarities = BTreeMap::new();
+    for rarity in app_s.all_id_rarity.values() {
+        *rarities.entry(*rarity).or_default() += 1;
+    }
+    let holders: HashSet<&String> = app_s.all_id_owner.values().collect();
+    Ok(Stats {
+        total_supply: app_s.main_state.total_supply.clone(),
+        holders: holders.len(),
+        stages,
+        rarities,
+        market: listing_stats(&app_s.market_id_price)?,
+        battle: listing_stats(&app_s.battle_id_price)?,
+        breed: listing_stats(&app_s.breed_id_price)?,
+    })
+}
+fn listing_stats(id_price: &HMStrings) -> Result<ListingStats, StateError> {
+    let mut prices = Vec::with_capacity(id_price.len());
+    for (id, price) in id_price {
+        prices.push(
+            price
+                .parse::<u128>()
+                .map_err(|_| StateError::Inconsistent(format!("id {} has price {}", id, price)))?,
+        );
+    }
+    prices.sort_unstable();
+    let len = prices.len();
+    // the mean of the two middle ones for an even count
+    let median = match len {
+        0 => None,
+        _ if !len.is_multiple_of(2) => Some(prices[len / 2]),
+        _ => Some(prices[len / 2 - 1].midpoint(prices[len / 2])),
+    };
+    Ok(ListingStats {
+        listed: len,
+        floor: prices.first().map(u128::to_string),
+        median: median.map(|x| x.to_string()),
+        max: prices.last().map(u128::to_string),
+    })
 }
 fn add_stats(fights_history: &mut HashMap<String, (u32, u32)>, winner: &str, loser: &str) {
     match fights_history.get_mut(winner) {
@@ -228,100 +555,86 @@ fn add_stats(fights_history: &mut HashMap<String, (u32, u32)>, winner: &str, los
         }
     }
 }
-pub async fn get_block_num() -> u128 {
-    loop {
-        let text = do_request(GETMIMIEPOCH, URL).await;
-        let response: Resp<String> = match serde_json::from_str(&text) {
-            Ok(result) => result,
-            Err(_) => continue,
-        };
-        match response.result.parse::<u128>() {
-            Ok(result) => return result,
-            Err(_) => continue,
-        }
-    }
+pub async fn get_block_num(config: &Config) -> Result<u128, StateError> {
+    let text = do_request(GETMIMIEPOCH, &config.rpc_url, config.rpc_retries).await?;
+    let response: Resp<String> = decode(&text, "mini epoch")?;
+    response
+        .result
+        .parse::<u128>()
+        .map_err(|e| StateError::Inconsistent(format!("mini epoch {}: {}", response.result, e)))
 }
-pub async fn update_state(start_num: u128, app_state: Arc<Mutex<Box<AppState>>>) {
+pub async fn update_state(shared: SharedState, start_num: u128) {
+    let config = &shared.config;
     let mut block_num = start_num;
-    let mut delay = 10;
+    // catch up with the chain right away, the state may be empty or from a snapshot
+    let mut delay = Duration::ZERO;
+    let mut failures = 0;
+    // an empty state is downloaded in full anyway, a snapshot only a few
+    // blocks old gets patched
+    let resync = Duration::from_secs(config.full_resync_secs);
+    let mut last_full = Instant::now();
     loop {
-        sleep(Duration::from_secs(delay)).await;
-        let cur_num = get_block_num().await;
-        if cur_num <= block_num {
-            if block_num % 100 == 0 {
-                delay = 10;
+        sleep(delay).await;
+        let result = match get_block_num(config).await {
+            Ok(cur_num) if cur_num <= block_num => {
+                if block_num.is_multiple_of(100) {
+                    delay = Duration::from_secs(10);
+                }
+                if delay == Duration::from_secs(1) {
+                    delay = Duration::from_secs(2);
+                } else {
+                    delay = Duration::from_secs(1);
+                }
+                continue;
             }
-            if delay == 1 {
-                delay = 2;
-            } else {
-                delay = 1;
+            Ok(cur_num) => {
+                let current = shared.app_state.load_full();
+                let full = last_full.elapsed() >= resync;
+                next_state(config, &current, block_num, cur_num, full)
+                    .await
+                    .map(|(state, was_full)| {
+                        if was_full {
+                            last_full = Instant::now();
+                        }
+                        (cur_num, state)
+                    })
+            }
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok((cur_num, mut new_state)) => {
+                new_state.version = shared.app_state.load().version + 1;
+                failures = 0;
+                delay = Duration::from_secs(25);
+                block_num = cur_num;
+                let new_state = Arc::new(new_state);
+                if !config.snapshot_path.is_empty() {
+                    // off the runtime, the whole state is written
+                    let (path, state) = (config.snapshot_path.clone(), Arc::clone(&new_state));
+                    let saved =
+                        spawn_blocking(move || snapshot::save(&path, block_num, &state)).await;
+                    if let Err(e) = saved.unwrap_or_else(|e| Err(std::io::Error::other(e))) {
+                        eprintln!("Cannot save snapshot {}: {}", config.snapshot_path, e);
+                    }
+                }
+                let time = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |x| x.as_secs());
+                // one diff for the sales and the subscribers
+                let events = shared.changes(&new_state);
+                let sales = sales::detect(&events, &new_state, block_num, time);
+                if !sales.is_empty() {
+                    sales::record(&shared, sales);
+                }
+                shared.replace_with(new_state, events);
+                archive::record(&shared, block_num, time).await;
+            }
+            // keep serving the last good state, block_num isn't moved
+            Err(e) => {
+                eprintln!("State refresh after block {} failed: {}", block_num, e);
+                delay = backoff(failures);
+                failures += 1;
             }
-            continue;
         }
-        delay = 25;
-        block_num = cur_num;
-        let new_state = create().await;
-        let mut cur_state = app_state.lock().unwrap();
-        *cur_state = Box::new(new_state);
-    }
-}
-// https://github.com/DeepDragons/dragon-zil/blob/master/src/mixins/utils.js#L50
-// most of visual gens have 2 parts - type (0-9) and color(0-4)
-// head have 1 digit
-// claws have 1 digit
-// Color Scheme have 3 digits
-// MutagenImutable have 3 digits
-// e.g. 777 03 03 43 31 14 33 44 11 73 1 4 110 158
-//      777 01 64 02 94 03 04 40 24 11 4 1 076 065
-// Aura-12   Horns-11   Scales-10   Spots-9   Tail-8   Wings-7
-// Spins-6   Body-5   Eyes-4   Head-3   Claws-2   Color Scheme-1   MutagenImutable-0
-/* https://github.com/DeepDragons/dragon-zil/blob/master/src/mixins/utils.js#L1
- * None      0
- * Common    1
- * Uncommon  2
- * Rare      3
- * Mythical  4
- * Legendary 5
- * Immortal  6
- * Arcana    7
- * Ancient   8
- */
-fn calc_rarity(gens: &str) -> u8 {
-    let gen_to_index = |a, b| gens[a..b].parse::<usize>().unwrap_or(0);
-    // https://github.com/DeepDragons/dragon-zil/blob/master/src/mixins/utils.js#L372
-    let rarity_sum = RI.aura[gen_to_index(3, 4)]
-        + RI.horns[gen_to_index(5, 6)]
-        + RI.scales[gen_to_index(7, 8)]
-        + RI.spots[gen_to_index(9, 10)]
-        + RI.tail[gen_to_index(11, 12)]
-        + RI.wings[gen_to_index(13, 14)]
-        + RI.body[gen_to_index(17, 18)]
-        + RI.eyes[gen_to_index(19, 20)]
-        + RI.head[gen_to_index(21, 22)];
-    match rarity_sum {
-        0..=15 => 0,  //TODO check it? "id":"2490" for example
-        16..=23 => 1, // Uncommon
-        24..=31 => 2, // Rare
-        32..=39 => 3, // Mythical
-        40..=47 => 4, // Legendary
-        48..=55 => 5, // Imortal
-        56..=63 => 6, // Arcana
-        _ => 7,       // Ancient
-    }
-}
-// "id":"2851"
-// 5271532761388019919425566412768461699999999998899999999999988999999999999996
-//  "id":"1894"
-//  17213176947417029247062885245301688801479160274101322991103071845030308089925
-fn calc_strength(gens: &str) -> u16 {
-    let len = gens.len();
-    let mut gens_defence: u128 = gens[len - 22..len - 2].parse::<u128>().unwrap_or(0);
-    let mut gens_attack: u128 = gens[len - 42..len - 22].parse::<u128>().unwrap_or(0);
-    let mut gens_sum = 0;
-    for _ in 0..20 {
-        gens_sum += (gens_defence % 100) as u16 + (gens_attack % 100) as u16;
-        gens_attack /= 100;
-        gens_defence /= 100;
     }
-    gens_sum
 }
@@ -0,0 +1,57 @@
+use crate::state::AppState;
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+// Bump on any change of AppState layout, old snapshots are ignored then.
+pub const SNAPSHOT_VERSION: u32 = 5;
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    version: u32,
+    block_num: u128,
+    state: &'a AppState,
+}
+
+#[derive(Deserialize)]
+struct SnapshotHeader {
+    version: u32,
+}
+
+#[derive(Deserialize)]
+struct Snapshot {
+    block_num: u128,
+    state: AppState,
+}
+
+// Written to a temp file and renamed, a crash never leaves a torn snapshot.
+pub fn save(path: &str, block_num: u128, state: &AppState) -> Result<(), Error> {
+    let tmp_path = format!("{}.tmp", path);
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    let snapshot = SnapshotRef {
+        version: SNAPSHOT_VERSION,
+        block_num,
+        state,
+    };
+    serde_json::to_writer(&mut writer, &snapshot).map_err(Error::other)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, path)
+}
+
+// None if there is no snapshot yet or it was written by another version.
+pub fn load(path: &str) -> Result<Option<(u128, AppState)>, Error> {
+    if path.is_empty() || !Path::new(path).exists() {
+        return Ok(None);
+    }
+    let text = fs::read_to_string(path)?;
+    // check the version first, the state of other versions may not be parsed at all
+    let header: SnapshotHeader =
+        serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    if header.version != SNAPSHOT_VERSION {
+        return Ok(None);
+    }
+    let snapshot: Snapshot =
+        serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    Ok(Some((snapshot.block_num, snapshot.state)))
+}
@@ -1,9 +1,14 @@
-use std::collections::HashMap;
+use crate::archive::Archive;
+use crate::config::Config;
+use crate::events::{diff, publish, ChangeEvent, EVENTS_BUFFER};
+use crate::sales::Sale;
+use crate::webhooks::WebhookStore;
+use arc_swap::ArcSwap;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::broadcast;
 
-pub const URL: &str = "https://api.zilliqa.com/";
-pub const APPOLO_URL: &str = "https://devex-apollo.zilliqa.com/";
-
-pub const GETFIGHT: &str = "{\"operationName\":\"Fights\",\"variables\":{\"contractAddr\":\"0x21b870dc77921b21f9a98a732786bf812888193c\",\"page\":1,\"perPage\":2147483647},\"query\":\"query Fights($contractAddr: String!, $page: Int, $perPage: Int) {txPagination(page: $page, perPage: $perPage, filter: {OR: [{toAddr: $contractAddr, receipt: {success: true, event_logs: {_eventname: \\\"AfterFightWinLose\\\"}}}]}, sort: TIMESTAMP_ASC) {pageInfo {currentPage perPage pageCount} items {receipt {event_logs { _eventname params {vname value}}}}}}\"}";
+pub const MAX_BACKOFF: u64 = 300; // seconds
 
 // https://dev.zilliqa.com/api/blockchain-related-methods/api-blockchain-get-current-mini-epoch/
 // Returns the current TX block number of the network.
@@ -12,15 +17,33 @@ pub const GETMIMIEPOCH: &str =
 
 // https://dev.zilliqa.com/api/contract-related-methods/api-contract-get-smartcontract-state/
 // Returns the state (mutable) variables of a smart contract address
-pub const MAINSTATE: &str = "{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"GetSmartContractState\",\"params\":[\"b4D83BECB950c096B001a3D1c7aBb10F571ae75f\"]}";
+pub const GETSTATE: &str = "GetSmartContractState";
 
 // https://dev.zilliqa.com/api/contract-related-methods/api-contract-get-smartcontract-state/
 // Returns the state (or a part specified) of a smart contract address
-pub const BATTLESTATE: &str = "{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"GetSmartContractSubState\",\"params\":[\"f0a3fbcfa48e4c796daafbb9c6341d68ff326b64\",\"waiting_list\",[]]}";
-pub const WOUNDSTATE: &str = "{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"GetSmartContractSubState\",\"params\":[\"f0a3fbcfa48e4c796daafbb9c6341d68ff326b64\",\"wounded_list\",[]]}";
-pub const BREEDSTATE: &str = "{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"GetSmartContractSubState\",\"params\":[\"ade7886ec4a36cb0a7de2f5d18cc7bdae12e3650\",\"waiting_list\",[]]}";
-pub const MARKETSTATE: &str = "{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"GetSmartContractSubState\",\"params\":[\"7b9b80aaF561Ecd4e89ea55D83d59Ab7aC01A575\",\"orderbook\",[]]}";
-pub const NAMESTATE: &str = "{\"id\":\"1\",\"jsonrpc\":\"2.0\",\"method\":\"GetSmartContractSubState\",\"params\":[\"0F5d8f74817E2BC5A09521149094A7860c691D42\",\"dragons_name\",[]]}";
+pub const GETSUBSTATE: &str = "GetSmartContractSubState";
+
+// https://dev.zilliqa.com/api/blockchain-related-methods/api-blockchain-get-tx-block/
+// Returns the details of a TX block (header.NumTxns is used)
+pub const GETTXBLOCK: &str = "GetTxBlock";
+
+// https://dev.zilliqa.com/api/transaction-related-methods/api-transaction-get-txbodies-for-tx-block/
+// Returns the validated transactions (with receipts) of a TX block,
+// errors out for a block without transactions
+pub const GETTXBODIES: &str = "GetTxnBodiesForTxBlock";
+
+// Successful transactions to the contract which emitted the event, oldest first.
+pub const EVENTS_QUERY: &str = "query Events($contractAddr: String!, $eventName: String!, $page: Int, $perPage: Int) {txPagination(page: $page, perPage: $perPage, filter: {OR: [{toAddr: $contractAddr, receipt: {success: true, event_logs: {_eventname: $eventName}}}]}, sort: TIMESTAMP_ASC) {pageInfo {currentPage perPage pageCount} items {ID receipt {event_logs { _eventname params {vname value}}}}}}";
+
+// https://github.com/DeepDragons/DragonZILContracts/blob/main/FightPlace.scilla
+pub const FIGHT_EVENT: &str = "AfterFightWinLose";
+
+// https://github.com/DeepDragons/DragonZILContracts/blob/main/BreedPlace.scilla
+// emitted when an egg is minted from the pair (father from waiting_list, mother)
+pub const BREED_EVENT: &str = "BreedSuccess";
+pub const BREED_FATHER: &str = "father_id";
+pub const BREED_MOTHER: &str = "mother_id";
+pub const BREED_CHILD: &str = "child_id";
 
 #[derive(Deserialize)]
 pub struct EventItem {
@@ -40,26 +63,33 @@ pub struct EventLogs {
 }
 
 #[derive(Deserialize)]
-pub struct Receipt {
+pub struct TxItem {
+    #[serde(rename = "ID")]
+    pub id: String,
     pub receipt: EventLogs,
 }
 
 #[derive(Deserialize)]
 pub struct PageInfo {
-    pub currentPage: u64,
-    pub perPage: u64,
-    pub pageCount: u64,
+    #[serde(rename = "currentPage")]
+    pub current_page: u64,
+    #[serde(rename = "perPage")]
+    pub per_page: u64,
+    #[serde(rename = "pageCount")]
+    pub page_count: u64,
 }
 
 #[derive(Deserialize)]
 pub struct TxPaginationItem {
-    pub pageInfo: PageInfo,
-    pub items: Vec<Receipt>,
+    #[serde(rename = "pageInfo")]
+    pub page_info: PageInfo,
+    pub items: Vec<TxItem>,
 }
 
 #[derive(Deserialize)]
 pub struct TxPagination {
-    pub txPagination: TxPaginationItem,
+    #[serde(rename = "txPagination")]
+    pub tx_pagination: TxPaginationItem,
 }
 
 #[derive(Deserialize)]
@@ -67,21 +97,77 @@ pub struct Data {
     pub data: TxPagination,
 }
 
-#[derive(Deserialize, Clone, Debug)]
-struct Dummy {
+#[derive(Deserialize)]
+pub struct TxBlockHeader {
+    #[serde(rename = "NumTxns")]
+    pub num_txns: u64,
+}
+
+#[derive(Deserialize)]
+pub struct TxBlock {
+    pub header: TxBlockHeader,
+}
+
+// event params are typed Scilla values (strings, lists, adts)
+#[derive(Deserialize)]
+pub struct TxEventParam {
+    pub vname: String,
+    pub value: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+pub struct TxEvent {
+    pub address: String,
+    pub _eventname: String,
+    #[serde(default)]
+    pub params: Vec<TxEventParam>,
+}
+
+#[derive(Deserialize)]
+pub struct TxReceipt {
+    pub success: bool,
+    #[serde(default)]
+    pub event_logs: Vec<TxEvent>,
+}
+
+#[derive(Deserialize)]
+pub struct TxBody {
+    #[serde(rename = "ID")]
+    pub id: String,
+    pub receipt: TxReceipt,
+}
+
+// Position in the (append only) list of transactions of some event.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct TxCursor {
+    pub processed: u64,
+    pub last_tx: String,
+}
+
+// State folded from Apollo event logs, carried between refreshes.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EventHistory {
+    pub fights: HashMap<String, (u32, u32)>,
+    pub fights_cursor: TxCursor,
+    pub parents: HMPairs,
+    pub breeds_cursor: TxCursor,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Dummy {
     // TODO vec strings
-    argtypes: [u8; 0],
-    arguments: [u8; 0],
-    constructor: String,
+    pub argtypes: [u8; 0],
+    pub arguments: [u8; 0],
+    pub constructor: String,
 }
 
 // https://github.com/DeepDragons/DragonZILContracts/blob/main/MarketPlace.scilla#L209
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct MarketItem {
-    argtypes: [u8; 0],
+    pub argtypes: [u8; 0],
     // Order of ByStr20 Uint128 Uint256 Uint256 (owner, price, id, order_id)
     pub arguments: [String; 4],
-    constructor: String,
+    pub constructor: String,
 }
 
 //https://github.com/DeepDragons/DragonZILContracts/blob/main/MarketPlace.scilla#L90
@@ -93,11 +179,11 @@ pub struct OrderState {
 
 // https://github.com/DeepDragons/DragonZILContracts/blob/main/BreedPlace.scilla#L256
 // waiting_list: Map Uint256 (Pair Uint128 ByStr20) (id -> (price, owner))
-#[derive(Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct BreedItem {
-    argtypes: [String; 2],
+    pub argtypes: [String; 2],
     pub arguments: [String; 2], // ar..s[0] is price, ar..s[1] is owner
-    constructor: String,
+    pub constructor: String,
 }
 
 #[derive(Deserialize)]
@@ -117,31 +203,31 @@ pub struct NameState {
 
 #[derive(Deserialize)]
 pub struct Resp<T> {
-    id: String,
-    jsonrpc: String,
+    pub id: String,
+    pub jsonrpc: String,
     pub result: T,
 }
 
-type HMStrings = HashMap<String, String>;
+pub type HMStrings = HashMap<String, String>;
 
 // https://github.com/DeepDragons/DragonZILContracts/blob/main/DragonZIL.scilla#L150
-#[derive(Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct MainState {
-    _balance: String,
-    cloud: String,
-    format_img: String,
-    max_stage: String,
-    migrate_option: Dummy,
-    minters: HashMap<String, Dummy>,
-    operator_approvals: HashMap<String, HashMap<String, Dummy>>,
+    pub _balance: String,
+    pub cloud: String,
+    pub format_img: String,
+    pub max_stage: String,
+    pub migrate_option: Dummy,
+    pub minters: HashMap<String, Dummy>,
+    pub operator_approvals: HashMap<String, HashMap<String, Dummy>>,
     // Map ByStr20 Uint25 (owner -> count)
-    owned_token_count: HMStrings,
-    token_approvals: HMStrings,
+    pub owned_token_count: HMStrings,
+    pub token_approvals: HMStrings,
     // Map Uint256 Uint256 (id -> gens)
     pub token_gen_battle: HMStrings,
     // Map Uint256 Uint256 (id -> gens)
     pub token_gen_image: HMStrings,
-    token_id_count: String,
+    pub token_id_count: String,
     //Map Uint256 ByStr20 (id -> owner)
     pub token_owners: HMStrings,
     // Map Uint256 Uint32 (id -> stage)
@@ -150,66 +236,148 @@ pub struct MainState {
     pub token_uris: HMStrings,
     // Map ByStr20 (Map Uint256 Uint32) (owner -> (id -> stage))
     pub tokens_owner_stage: HashMap<String, HMStrings>,
-    total_supply: String,
+    pub total_supply: String,
 }
 
-type HMVecStrings = HashMap<String, Vec<String>>;
+pub type HMVecStrings = HashMap<String, Vec<String>>;
+pub type HMPairs = HashMap<String, (String, String)>;
+
+// Raw contract states AppState is derived from.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ContractStates {
+    pub main: MainState,
+    // FightPlace waiting_list (id -> price)
+    pub battle: HMStrings,
+    // FightPlace wounded_list (id -> Vec<wound>)
+    pub wounds: HMVecStrings,
+    // BreedPlace waiting_list (id -> (price, owner))
+    pub breed: HashMap<String, BreedItem>,
+    // MarketPlace orderbook (order_id -> Order)
+    pub orderbook: HashMap<String, MarketItem>,
+    // dragons_name (id -> name)
+    pub names: HMStrings,
+}
 
-#[derive(Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct AppState {
+    pub version: u64, // bumped by every swap of the shared state
     pub all_id_list: Vec<String>,
     pub all_owned_id: HMVecStrings, //                 (owner -> Vec<id>)
     pub all_id_owner: HMStrings,    //                 (id -> Owner)
     pub all_id_rarity: HashMap<String, u8>, //         (id -> rarity)
     pub all_id_strength: HashMap<String, u16>, //      (id -> strength)
+    pub all_id_combat: HashMap<String, (u16, u16)>, // (id -> (attack, defence))
     pub all_id_fights: HashMap<String, (u32, u32)>, // (id -> (win, lose))
+    pub fights_cursor: TxCursor,
+    pub all_id_parents: HMPairs, //                    (id -> (father, mother))
+    pub all_id_children: HMVecStrings, //              (id -> Vec<child>)
+    pub breeds_cursor: TxCursor,
     pub all_id_wounds: HMVecStrings, //                (id -> Vec<wound>)
     pub main_state: MainState,
     pub battle_id_list: Vec<String>,
     pub battle_id_price: HMStrings,    //              (id -> price)
     pub battle_owned_id: HMVecStrings, //              (owner -> Vec<id>)
+    pub breed_state: HashMap<String, BreedItem>, //    raw breed waiting_list
     pub breed_id_list: Vec<String>,
     pub breed_id_price: HMStrings,    //               (id -> price)
     pub breed_owned_id: HMVecStrings, //               (owner -> Vec<id>)
+    pub orderbook: HashMap<String, MarketItem>, //     raw market orderbook
     pub market_id_list: Vec<String>,
     pub market_id_price: HMStrings,    //              (id -> price)
     pub market_id_order: HMStrings,    //              (id -> order_id)
     pub market_owned_id: HMVecStrings, //              (owner -> Vec<id>)
     pub id_name: HMStrings,            //              (id -> name)
+    pub name_index: Vec<(String, String)>, //          (lowercase name, id) by name
+    pub stats: Stats,
 }
 
-/*
- * https://github.com/DeepDragons/dragon-zil/blob/master/src/mixins/utils.js
- * None      0
- * Common    1
- * Uncommon  2
- * Rare      3
- * Mythical  4
- * Legendary 5
- * Immortal  6
- * Arcana    7
- * Ancient   8
- */
-pub const RI: RarityConst = RarityConst {
-    aura: [0, 2, 3, 3, 4, 5],
-    horns: [0, 2, 3, 3, 3, 3, 4, 5],
-    scales: [1, 2, 3, 4, 5],
-    spots: [0, 1, 2, 2, 2, 2, 2, 2, 8, 5],
-    tail: [0, 2, 3, 3, 3, 3, 3, 4, 5],
-    wings: [0, 1, 2, 3, 4, 5],
-    body: [0, 1, 4, 6],
-    eyes: [0, 1, 3, 3, 3, 3, 4, 4, 5, 6],
-    head: [0, 1, 3, 5, 6, 7],
-};
-
-pub struct RarityConst {
-    pub aura: [u8; 6],
-    pub horns: [u8; 8],
-    pub scales: [u8; 5],
-    pub spots: [u8; 10],
-    pub tail: [u8; 9],
-    pub wings: [u8; 6],
-    pub body: [u8; 4],
-    pub eyes: [u8; 10],
-    pub head: [u8; 6],
+// Collection totals, computed once per refresh.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Stats {
+    pub total_supply: String,
+    pub holders: usize,
+    pub stages: BTreeMap<u8, usize>,   // stage -> count
+    pub rarities: BTreeMap<u8, usize>, // rarity -> count
+    pub market: ListingStats,
+    pub battle: ListingStats,
+    pub breed: ListingStats,
 }
+
+// Prices of a waiting list or the orderbook, None when it is empty.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ListingStats {
+    pub listed: usize,
+    pub floor: Option<String>,
+    pub median: Option<String>,
+    pub max: Option<String>,
+}
+
+impl AppState {
+    pub fn contract_states(&self) -> ContractStates {
+        ContractStates {
+            main: self.main_state.clone(),
+            battle: self.battle_id_price.clone(),
+            wounds: self.all_id_wounds.clone(),
+            breed: self.breed_state.clone(),
+            orderbook: self.orderbook.clone(),
+            names: self.id_name.clone(),
+        }
+    }
+    pub fn history(&self) -> EventHistory {
+        EventHistory {
+            fights: self.all_id_fights.clone(),
+            fights_cursor: self.fights_cursor.clone(),
+            parents: self.all_id_parents.clone(),
+            breeds_cursor: self.breeds_cursor.clone(),
+        }
+    }
+}
+
+// Shared by the handlers and the refresher. Readers take the current snapshot
+// and never block each other, the refresher swaps in a new one atomically
+// and sends what changed to the events subscribers.
+pub struct Shared {
+    pub config: Config,
+    pub app_state: ArcSwap<AppState>,
+    pub events: broadcast::Sender<ChangeEvent>,
+    pub webhooks: Mutex<WebhookStore>,
+    pub sales: RwLock<Vec<Sale>>, // in the order they were made
+    pub archive: Mutex<Archive>,
+}
+
+impl Shared {
+    pub fn new(config: Config, app_state: AppState) -> SharedState {
+        Arc::new(Shared {
+            config,
+            app_state: ArcSwap::from_pointee(app_state),
+            events: broadcast::channel(EVENTS_BUFFER).0,
+            webhooks: Mutex::new(WebhookStore::default()),
+            sales: RwLock::new(Vec::new()),
+            archive: Mutex::new(Archive::default()),
+        })
+    }
+    // the events from the current state to the next, none after a cold
+    // start: against the empty bootstrap state (version 0) every dragon
+    // would look minted and every order listed
+    pub fn changes(&self, next: &AppState) -> Vec<ChangeEvent> {
+        let current = self.app_state.load();
+        if current.version == 0 {
+            return Vec::new();
+        }
+        diff(&current, next)
+    }
+
+    // swaps in the next state and publishes the difference
+    pub fn replace(&self, app_state: AppState) {
+        let events = self.changes(&app_state);
+        self.replace_with(Arc::new(app_state), events);
+    }
+
+    // same, with the events already taken by changes
+    pub fn replace_with(&self, app_state: Arc<AppState>, events: Vec<ChangeEvent>) {
+        self.app_state.store(app_state);
+        publish(&self.events, events);
+    }
+}
+
+pub type SharedState = Arc<Shared>;
@@ -2,3 +2,5 @@ pub mod structs;
 pub use structs::*;
 pub mod routes;
 pub use routes::*;
+pub mod server;
+pub use server::build_server;
@@ -1,20 +1,48 @@
-use crate::state::AppState;
-use crate::web_api::{Handler, Item, OkResponse, Page, Pagination};
-use std::collections::HashMap;
-use std::sync::{Arc, Mutex};
+use crate::address::{normalize, to_bech32};
+use crate::archive::{self, At, Found};
+use crate::events::EventFilter;
+use crate::genes::{decode_combat, decode_image, ImageGenes, COLORED_SECTIONS, SECTION_NAMES};
+use crate::sales::{candles, volume, Sale};
+use crate::state::{AppState, Shared, SharedState};
+use crate::web_api::{
+    Analytics, AnalyticsQuery, AnalyticsResponse, ArchiveResponse, AtQuery, BatchResponse,
+    CombatResponse, Cursor, EventsQuery, FamilyNode, FamilyQuery, FamilyResponse, Fighter, Floors,
+    GenesResponse, Handler, Item, Listing, Matchup, OkResponse, OwnerProfile, OwnerResponse, Page,
+    Pagination, Pairing, Parent, PreviewQuery, PreviewResponse, SalesResponse, SearchQuery,
+    ShortItem, SimulateQuery, SimulateResponse, SortField, SortKey, SortValue, StatsResponse,
+    TraitFilter, Volumes, WebhookInfo, WebhookRequest, WebhookResponse, MAX_CANDLES,
+    MAX_FAMILY_DEPTH,
+};
+use crate::webhooks::delivery::{lock, persist};
+use crate::webhooks::{check_url, MIN_SECRET_LEN};
+use async_tungstenite::tungstenite::handshake::derive_accept_key;
+use async_tungstenite::tungstenite::protocol::{Message, Role};
+use async_tungstenite::WebSocketStream;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::json;
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
 use tide::{Request, Response, StatusCode};
+use tokio::sync::broadcast::error::RecvError;
 
-// GET /api/v1/dragons/:id
-pub async fn get_dragon_by_id(req: Request<Arc<Mutex<Box<AppState>>>>) -> tide::Result {
+// GET /api/v1/dragons/:id [?expand=genes]
+pub async fn get_dragon_by_id(req: Request<SharedState>) -> tide::Result {
     let str_id = req.param("id")?;
-    let app_state = &req.state().lock().unwrap();
+    let query: Page = req.query()?;
+    let app_state = &load_state(&req).await?;
     match app_state.main_state.token_stage.get(str_id) {
         Some(_) => {
             let page = Page {
                 limit: 1,
+                expand: query.expand,
+                bech32: query.bech32,
                 ..Default::default()
             };
-            Ok(create_response(vec![create_item(str_id, app_state)?], &page, 1)?.into())
+            let ids = [str_id.to_string()];
+            let items = collect_items(&ids, &page, app_state)?;
+            Ok(create_response(items, create_pagination(&page, 1, 0, None))?.into())
         }
         None => Ok(create_error(
             StatusCode::NotFound,
@@ -23,31 +51,628 @@ pub async fn get_dragon_by_id(req: Request<Arc<Mutex<Box<AppState>>>>) -> tide::
     }
 }
 
+// GET /api/v1/dragons/:id/family [?depth=2]
+pub async fn get_dragon_family(req: Request<SharedState>) -> tide::Result {
+    let str_id = req.param("id")?;
+    let query: FamilyQuery = req.query()?;
+    if query.depth == 0 || query.depth > MAX_FAMILY_DEPTH {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            &format!("Depth should be from 1 to {}.", MAX_FAMILY_DEPTH),
+        ));
+    }
+    let app_state = &load_state(&req).await?;
+    if !app_state.main_state.token_stage.contains_key(str_id) {
+        return Ok(create_error(
+            StatusCode::NotFound,
+            &format!("Id {} is not found.", str_id),
+        ));
+    }
+    let result = FamilyResponse {
+        success: true,
+        data: family_node(str_id, query.depth, (true, true), app_state)?,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
+// GET /api/v1/dragons/:id/genes
+pub async fn get_dragon_genes(req: Request<SharedState>) -> tide::Result {
+    let str_id = req.param("id")?;
+    let app_state = &load_state(&req).await?;
+    let gen_image = match app_state.main_state.token_gen_image.get(str_id) {
+        Some(gen_image) => gen_image,
+        None => {
+            return Ok(create_error(
+                StatusCode::NotFound,
+                &format!("Id {} is not found.", str_id),
+            ))
+        }
+    };
+    let result = GenesResponse {
+        success: true,
+        data: decode_image(gen_image).ok_or_else(internal_error)?,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
+// GET /api/v1/dragons/:id/combat
+pub async fn get_dragon_combat(req: Request<SharedState>) -> tide::Result {
+    let str_id = req.param("id")?;
+    let app_state = &load_state(&req).await?;
+    let gen_battle = match app_state.main_state.token_gen_battle.get(str_id) {
+        Some(gen_battle) => gen_battle,
+        None => {
+            return Ok(create_error(
+                StatusCode::NotFound,
+                &format!("Id {} is not found.", str_id),
+            ))
+        }
+    };
+    let result = CombatResponse {
+        success: true,
+        data: decode_combat(gen_battle).ok_or_else(internal_error)?,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
 // GET /api/v1/battle
-pub async fn get_from_battle(req: Request<Arc<Mutex<Box<AppState>>>>) -> tide::Result {
-    get_priced_dragons(&Handler::Battle, &req)
+pub async fn get_from_battle(req: Request<SharedState>) -> tide::Result {
+    get_priced_dragons(&Handler::Battle, &req).await
+}
+
+// GET /api/v1/battle/simulate ?attacker=1[&defender=2]
+// The combat genes and wounds of both sides, the FightPlace resolution isn't
+// ported, so no outcome is predicted.
+pub async fn get_battle_simulation(req: Request<SharedState>) -> tide::Result {
+    let query: SimulateQuery = req.query()?;
+    if query.attacker.is_empty() {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            "Attacker is required.",
+        ));
+    }
+    if query.defender == query.attacker {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            "Attacker and defender should differ.",
+        ));
+    }
+    let app_state = &load_state(&req).await?;
+    let has_genes = |str_id: &str| app_state.main_state.token_gen_battle.contains_key(str_id);
+    // only the ids asked for are errors, the waiting list is taken as it is
+    for str_id in [&query.attacker, &query.defender] {
+        if !str_id.is_empty() && !has_genes(str_id) {
+            return Ok(create_error(
+                StatusCode::NotFound,
+                &format!("Id {} is not found.", str_id),
+            ));
+        }
+    }
+    let defenders: Vec<&str> = if query.defender.is_empty() {
+        app_state
+            .battle_id_list
+            .iter()
+            .map(|x| x.as_str())
+            .filter(|x| *x != query.attacker && has_genes(x))
+            .collect()
+    } else {
+        vec![&query.defender]
+    };
+    let mut data = Vec::with_capacity(defenders.len());
+    for defender_id in defenders {
+        data.push(Matchup {
+            attacker: create_fighter(&query.attacker, app_state)?,
+            defender: create_fighter(defender_id, app_state)?,
+        });
+    }
+    // weakest defenders first
+    data.sort_by_key(|x| x.defender.genes.strength);
+    let result = SimulateResponse {
+        success: true,
+        data,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
 }
 
 // GET /api/v1/breed
-pub async fn get_from_breed(req: Request<Arc<Mutex<Box<AppState>>>>) -> tide::Result {
-    get_priced_dragons(&Handler::Breed, &req)
+pub async fn get_from_breed(req: Request<SharedState>) -> tide::Result {
+    get_priced_dragons(&Handler::Breed, &req).await
+}
+
+// GET /api/v1/breed/preview ?mother=1[&father=2]
+// The genes of both parents, the BreedPlace mixing isn't ported, so no child
+// is predicted. Like BreedPlace, eggs can't breed and the father has to be on
+// the breed waiting list.
+pub async fn get_breed_preview(req: Request<SharedState>) -> tide::Result {
+    let query: PreviewQuery = req.query()?;
+    if query.mother.is_empty() {
+        return Ok(create_error(StatusCode::BadRequest, "Mother is required."));
+    }
+    if query.father == query.mother {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            "Father and mother should differ.",
+        ));
+    }
+    let app_state = &load_state(&req).await?;
+    let stage = |str_id: &str| app_state.main_state.token_stage.get(str_id);
+    // only the ids asked for are errors, the waiting list is taken as it is
+    for str_id in [&query.mother, &query.father] {
+        match stage(str_id) {
+            _ if str_id.is_empty() => {}
+            None => {
+                return Ok(create_error(
+                    StatusCode::NotFound,
+                    &format!("Id {} is not found.", str_id),
+                ))
+            }
+            Some(stage) if stage == "0" => {
+                return Ok(create_error(
+                    StatusCode::BadRequest,
+                    &format!("Id {} is an egg.", str_id),
+                ))
+            }
+            Some(_) => {}
+        }
+    }
+    if !query.father.is_empty() && !app_state.breed_id_price.contains_key(&query.father) {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            &format!("Id {} is not on the breed waiting list.", query.father),
+        ));
+    }
+    let fathers: Vec<&str> = if query.father.is_empty() {
+        app_state
+            .breed_id_list
+            .iter()
+            .map(|x| x.as_str())
+            .filter(|x| *x != query.mother && stage(x).is_some_and(|x| x != "0"))
+            .collect()
+    } else {
+        vec![&query.father]
+    };
+    let mut data = Vec::with_capacity(fathers.len());
+    for father_id in fathers {
+        data.push(Pairing {
+            father: create_parent(father_id, app_state)?,
+            mother: create_parent(&query.mother, app_state)?,
+            price: app_state.breed_id_price.get(father_id).map(|x| x.as_str()),
+        });
+    }
+    // rarest and strongest fathers first
+    data.sort_by_key(|x| std::cmp::Reverse((x.father.genes.rarity, x.father.combat.strength)));
+    let result = PreviewResponse {
+        success: true,
+        data,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
+// GET /api/v1/owners/:address [?bech32=true]
+pub async fn get_owner(req: Request<SharedState>) -> tide::Result {
+    let query: Page = req.query()?;
+    let address = &match normalize(req.param("address")?) {
+        Ok(address) => address,
+        Err(e) => {
+            return Ok(create_error(
+                StatusCode::BadRequest,
+                &format!("Bad address: {}.", e),
+            ))
+        }
+    };
+    let app_state = &load_state(&req).await?;
+    let tokens = app_state
+        .all_owned_id
+        .get(address)
+        .map_or(&[][..], |x| x.as_slice());
+    let floors = market_floors(app_state, |id| {
+        Ok(*get_element(&app_state.all_id_rarity, id)?)
+    })?;
+    let mut data = OwnerProfile {
+        address: if query.bech32 {
+            to_bech32(address)
+        } else {
+            address.clone()
+        },
+        dragons: tokens.len(),
+        stages: BTreeMap::new(),
+        rarities: BTreeMap::new(),
+        market: owner_listings(
+            address,
+            &app_state.market_owned_id,
+            &app_state.market_id_price,
+        ),
+        battle: owner_listings(
+            address,
+            &app_state.battle_owned_id,
+            &app_state.battle_id_price,
+        ),
+        breed: owner_listings(
+            address,
+            &app_state.breed_owned_id,
+            &app_state.breed_id_price,
+        ),
+        fights_win: 0,
+        fights_lose: 0,
+        portfolio_value: String::new(),
+    };
+    let mut value: u128 = 0;
+    for str_id in tokens {
+        let stage = get_element(&app_state.main_state.token_stage, str_id)?
+            .parse()
+            .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+        let rarity = *get_element(&app_state.all_id_rarity, str_id)?;
+        *data.stages.entry(stage).or_default() += 1;
+        *data.rarities.entry(rarity).or_default() += 1;
+        let (win, lose) = get_element_or_zero(&app_state.all_id_fights, str_id);
+        data.fights_win += win;
+        data.fights_lose += lose;
+        // a rarity nobody sells is valued at the overall floor
+        value += floors.get(&rarity).or(floors.get(&u8::MAX)).unwrap_or(&0);
+    }
+    data.portfolio_value = value.to_string();
+    let result = OwnerResponse {
+        success: true,
+        data,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
+// GET /api/v1/stats
+pub async fn get_stats(req: Request<SharedState>) -> tide::Result {
+    let app_state = &load_state(&req).await?;
+    let result = StatsResponse {
+        success: true,
+        data: &app_state.stats,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
+// GET /api/v1/archive
+// Blocks (and times) of the states ?at_block= and ?at_time= can be served from.
+pub async fn get_archive(req: Request<SharedState>) -> tide::Result {
+    let checkpoints = archive::lock(req.state()).checkpoints.clone();
+    let result = ArchiveResponse {
+        success: true,
+        data: &checkpoints,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
+// GET /api/v1/events [?owner=0x...&id=1,2&kind=listed,transfer]
+// Server-Sent Events, named by the kind, with the state version as the id.
+pub async fn get_events(req: Request<SharedState>) -> tide::Result {
+    let filter = parse_event_filter(&req)?;
+    let events = req.state().events.subscribe();
+    // the handler is Fn, but there is only one call per connection
+    let events = std::sync::Mutex::new(Some(events));
+    Ok(tide::sse::upgrade(req, move |_req, sender| {
+        let filter = filter.clone();
+        let events = events.lock().ok().and_then(|mut x| x.take());
+        async move {
+            let mut events = match events {
+                Some(events) => events,
+                None => return Ok(()),
+            };
+            loop {
+                match events.recv().await {
+                    Ok(event) if filter.matches(&event) => {
+                        let data = serde_json::to_string(&event)?;
+                        let id = event.version.to_string();
+                        sender.send(event.kind.as_str(), data, Some(&id)).await?;
+                    }
+                    Ok(_) => {}
+                    Err(RecvError::Lagged(missed)) => {
+                        let data = format!("{{\"missed\":{}}}", missed);
+                        sender.send("lagged", data, None).await?;
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                }
+            }
+        }
+    }))
+}
+
+// GET /api/v1/events/ws [?owner=0x...&id=1,2&kind=listed,transfer]
+// The same events as JSON text messages over a WebSocket.
+pub async fn get_events_ws(req: Request<SharedState>) -> tide::Result {
+    let filter = parse_event_filter(&req)?;
+    let is_websocket = req
+        .header("Upgrade")
+        .is_some_and(|x| x.as_str().eq_ignore_ascii_case("websocket"));
+    let key = match (is_websocket, req.header("Sec-WebSocket-Key")) {
+        (true, Some(key)) => key.as_str().to_string(),
+        _ => {
+            return Ok(create_error(
+                StatusCode::BadRequest,
+                "WebSocket upgrade is required.",
+            ))
+        }
+    };
+    let mut events = req.state().events.subscribe();
+    let mut res = Response::new(StatusCode::SwitchingProtocols);
+    res.insert_header("Upgrade", "websocket");
+    res.insert_header("Connection", "Upgrade");
+    res.insert_header("Sec-WebSocket-Accept", derive_accept_key(key.as_bytes()));
+    let http_res: &mut tide::http::Response = res.as_mut();
+    let upgrade = http_res.recv_upgrade().await;
+    async_std::task::spawn(async move {
+        let connection = match upgrade.await {
+            Some(connection) => connection,
+            None => return,
+        };
+        let ws = WebSocketStream::from_raw_socket(connection, Role::Server, None).await;
+        let (mut sink, mut stream) = ws.split();
+        // reading answers pings and notices the close
+        async_std::task::spawn(async move { while let Some(Ok(_)) = stream.next().await {} });
+        loop {
+            let text = match events.recv().await {
+                Ok(event) if filter.matches(&event) => match serde_json::to_string(&event) {
+                    Ok(text) => text,
+                    Err(_) => continue,
+                },
+                Ok(_) => continue,
+                Err(RecvError::Lagged(missed)) => {
+                    format!("{{\"kind\":\"lagged\",\"missed\":{}}}", missed)
+                }
+                Err(RecvError::Closed) => return,
+            };
+            if sink.send(Message::Text(text)).await.is_err() {
+                return;
+            }
+        }
+    });
+    Ok(res)
+}
+
+// POST /api/v1/webhooks {"url": "https://...", "secret": "...",
+// "owner": "0x...", "ids": ["1"], "kinds": ["listed"], "max_price": "1000"}
+pub async fn post_webhook(mut req: Request<SharedState>) -> tide::Result {
+    let mut body: WebhookRequest = match req.body_json().await {
+        Ok(body) => body,
+        Err(_) => {
+            return Ok(create_error(
+                StatusCode::BadRequest,
+                "Body should be a webhook with url and secret.",
+            ))
+        }
+    };
+    let scheme = reqwest::Url::parse(&body.url).map(|x| x.scheme().to_string());
+    if !matches!(scheme.as_deref(), Ok("http") | Ok("https")) {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            "Url should be http(s).",
+        ));
+    }
+    if body.secret.len() < MIN_SECRET_LEN {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            &format!("Secret should be {} characters at least.", MIN_SECRET_LEN),
+        ));
+    }
+    if !body.filter.owner.is_empty() {
+        body.filter.owner = match normalize(&body.filter.owner) {
+            Ok(owner) => owner,
+            Err(e) => {
+                return Ok(create_error(
+                    StatusCode::BadRequest,
+                    &format!("Bad owner: {}.", e),
+                ))
+            }
+        };
+    }
+    if let Some(max_price) = &body.filter.max_price {
+        if max_price.parse::<u128>().is_err() {
+            return Ok(create_error(StatusCode::BadRequest, "Bad max_price."));
+        }
+    }
+    // the last check, it may take a DNS lookup
+    if !req.state().config.webhook_allow_private {
+        let url = body.url.clone();
+        if let Err(e) = async_std::task::spawn_blocking(move || check_url(&url)).await {
+            return Ok(create_error(
+                StatusCode::BadRequest,
+                &format!("Url should be public: {}.", e),
+            ));
+        }
+    }
+    let nonce = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_nanos());
+    let result = {
+        let mut store = lock(req.state());
+        let hook = store.add(body.url, body.secret, body.filter, nonce);
+        serde_json::to_string(&WebhookResponse {
+            success: true,
+            data: WebhookInfo {
+                id: &hook.id,
+                url: &hook.url,
+                filter: &hook.filter,
+            },
+        })
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+    };
+    persist(req.state());
+    Ok(result.into())
+}
+
+// GET /api/v1/webhooks/:id
+pub async fn get_webhook(req: Request<SharedState>) -> tide::Result {
+    let id = req.param("id")?;
+    let store = lock(req.state());
+    match store.get(id) {
+        Some(hook) => Ok(serde_json::to_string(&WebhookResponse {
+            success: true,
+            data: WebhookInfo {
+                id: &hook.id,
+                url: &hook.url,
+                filter: &hook.filter,
+            },
+        })
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into()),
+        None => Ok(create_error(
+            StatusCode::NotFound,
+            &format!("Webhook {} is not found.", id),
+        )),
+    }
+}
+
+// DELETE /api/v1/webhooks/:id, undelivered payloads are dropped
+pub async fn delete_webhook(req: Request<SharedState>) -> tide::Result {
+    let id = req.param("id")?;
+    if !lock(req.state()).remove(id) {
+        return Ok(create_error(
+            StatusCode::NotFound,
+            &format!("Webhook {} is not found.", id),
+        ));
+    }
+    persist(req.state());
+    Ok("{\"success\":true}".into())
+}
+
+// GET /api/v1/market/history [?from=0&to=1700000000&min_rarity=1&stage=1&owner=0x...]
+// Newest first, the owner is the buyer or the seller.
+pub async fn get_market_history(req: Request<SharedState>) -> tide::Result {
+    reject_at(&req)?;
+    let page = &parse_page(&req)?;
+    create_sales(page, None, req.state())
+}
+
+// GET /api/v1/market/analytics [?interval=4h&candles=30]
+pub async fn get_market_analytics(req: Request<SharedState>) -> tide::Result {
+    reject_at(&req)?;
+    let query: AnalyticsQuery = req.query()?;
+    let interval = match parse_interval(&query.interval) {
+        Some(interval) => interval,
+        None => {
+            return Ok(create_error(
+                StatusCode::BadRequest,
+                "Interval should be seconds or a number with m, h, d or w.",
+            ))
+        }
+    };
+    if query.candles == 0 || query.candles > MAX_CANDLES {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            &format!("Candles should be from 1 to {}.", MAX_CANDLES),
+        ));
+    }
+    let app_state = &req.state().app_state.load_full();
+    let mut by_rarity = market_floors(app_state, |id| {
+        Ok(*get_element(&app_state.all_id_rarity, id)?)
+    })?;
+    let overall = by_rarity.remove(&u8::MAX);
+    let mut by_stage = market_floors(app_state, |id| {
+        get_element(&app_state.main_state.token_stage, id)?
+            .parse()
+            .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))
+    })?;
+    by_stage.remove(&u8::MAX);
+    let to_strings = |floors: BTreeMap<u8, u128>| -> BTreeMap<u8, String> {
+        floors
+            .into_iter()
+            .map(|(k, v)| (k, v.to_string()))
+            .collect()
+    };
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs());
+    const DAY: u64 = 24 * 60 * 60;
+    let since =
+        (now - now % interval).saturating_sub(interval.saturating_mul(query.candles as u64 - 1));
+    let sales =
+        req.state().sales.read().map_err(|_| {
+            tide::Error::from_str(StatusCode::InternalServerError, "Sales are locked.")
+        })?;
+    let result = AnalyticsResponse {
+        success: true,
+        data: Analytics {
+            floor: Floors {
+                overall: overall.map(|x| x.to_string()),
+                by_stage: to_strings(by_stage),
+                by_rarity: to_strings(by_rarity),
+            },
+            volume: Volumes {
+                day: volume(&sales, now.saturating_sub(DAY)),
+                week: volume(&sales, now.saturating_sub(7 * DAY)),
+                month: volume(&sales, now.saturating_sub(30 * DAY)),
+            },
+            interval,
+            candles: candles(&sales, interval, since),
+        },
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
+// GET /api/v1/dragons/:id/history [?from=0&to=1700000000]
+pub async fn get_dragon_history(req: Request<SharedState>) -> tide::Result {
+    reject_at(&req)?;
+    let str_id = req.param("id")?;
+    let page = &parse_page(&req)?;
+    let app_state = req.state().app_state.load();
+    if !app_state.main_state.token_stage.contains_key(str_id) {
+        return Ok(create_error(
+            StatusCode::NotFound,
+            &format!("Id {} is not found.", str_id),
+        ));
+    }
+    create_sales(page, Some(str_id), req.state())
 }
 
 // GET /api/v1/market
-pub async fn get_from_market(req: Request<Arc<Mutex<Box<AppState>>>>) -> tide::Result {
-    get_priced_dragons(&Handler::Market, &req)
+pub async fn get_from_market(req: Request<SharedState>) -> tide::Result {
+    get_priced_dragons(&Handler::Market, &req).await
 }
 
 // GET /api/v1/dragons [?limit=1&offset=1&owner=0x...]
-pub async fn get_dragons(req: Request<Arc<Mutex<Box<AppState>>>>) -> tide::Result {
-    let app_state = &req.state().lock().unwrap();
-    let page: &Page = &req.query()?;
+pub async fn get_dragons(req: Request<SharedState>) -> tide::Result {
+    let app_state = &load_state(&req).await?;
+    let page = &parse_page(&req)?;
+    if !page.ids.is_empty() {
+        let ids: Vec<String> = page.ids.split(',').map(|x| x.trim().to_string()).collect();
+        return create_batch(ids, page, req.state().config.max_batch_size, app_state);
+    }
     if page.owner.is_empty() {
-        create_dragons(&app_state.all_id_list, page, app_state)
+        if !page.has_filters() && page.sort_by.is_empty() {
+            return create_dragons(&app_state.all_id_list, page, None, app_state);
+        }
+        create_dragons(
+            &filter_n_sort(
+                &app_state.all_id_list,
+                false,
+                &app_state.main_state.token_stage,
+                page,
+                app_state,
+            )?,
+            page,
+            None,
+            app_state,
+        )
     } else {
         let tokens = match app_state.all_owned_id.get(&page.owner) {
             Some(result) => result,
-            None => return Ok(create_response(vec![], page, 0)?.into()),
+            None => {
+                return Ok(create_response(vec![], create_pagination(page, 0, 0, None))?.into())
+            }
         };
         create_dragons(
             &filter_n_sort(
@@ -58,11 +683,148 @@ pub async fn get_dragons(req: Request<Arc<Mutex<Box<AppState>>>>) -> tide::Resul
                 app_state,
             )?,
             page,
+            None,
             app_state,
         )
     }
 }
 
+// GET /api/v1/dragons/search ?q=smau [&limit=6&offset=0...]
+// names starting with q first, then the ones containing it
+pub async fn search_dragons(req: Request<SharedState>) -> tide::Result {
+    let query: SearchQuery = req.query()?;
+    let q = query.q.trim().to_lowercase();
+    if q.is_empty() {
+        return Ok(create_error(StatusCode::BadRequest, "Query is required."));
+    }
+    let mut page = parse_page(&req)?;
+    // relevance has no sort keys to make a cursor of
+    page.unkeyed = page.sort_by.is_empty();
+    if page.unkeyed && page.after.is_some() {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            "Cursor needs a sort here.",
+        ));
+    }
+    let page = &page;
+    let app_state = &load_state(&req).await?;
+    let index = &app_state.name_index;
+    let start = index.partition_point(|(name, _)| name.as_str() < q.as_str());
+    let mut tokens: Vec<String> = index[start..]
+        .iter()
+        .take_while(|(name, _)| name.starts_with(&q))
+        .map(|(_, id)| id.clone())
+        .collect();
+    tokens.extend(
+        index
+            .iter()
+            .filter(|(name, _)| !name.starts_with(&q) && name.contains(&q))
+            .map(|(_, id)| id.clone()),
+    );
+    if !page.has_filters() && page.sort_by.is_empty() {
+        return create_dragons(&tokens, page, None, app_state);
+    }
+    create_dragons(
+        &filter_n_sort(
+            &tokens,
+            false,
+            &app_state.main_state.token_stage,
+            page,
+            app_state,
+        )?,
+        page,
+        None,
+        app_state,
+    )
+}
+
+// POST /api/v1/dragons/batch ["1", "2", 3] [?expand=genes]
+pub async fn post_dragons_batch(mut req: Request<SharedState>) -> tide::Result {
+    let body: Vec<serde_json::Value> = match req.body_json().await {
+        Ok(body) => body,
+        Err(_) => {
+            return Ok(create_error(
+                StatusCode::BadRequest,
+                "Body should be a JSON array of ids.",
+            ))
+        }
+    };
+    let mut ids = Vec::with_capacity(body.len());
+    for id in body {
+        match id {
+            serde_json::Value::String(id) => ids.push(id),
+            serde_json::Value::Number(id) if id.is_u64() => ids.push(id.to_string()),
+            _ => {
+                return Ok(create_error(
+                    StatusCode::BadRequest,
+                    "Ids should be strings or numbers.",
+                ))
+            }
+        }
+    }
+    let page = &parse_page(&req)?;
+    let app_state = &load_state(&req).await?;
+    create_batch(ids, page, req.state().config.max_batch_size, app_state)
+}
+
+fn create_sales(page: &Page, str_id: Option<&str>, shared: &Shared) -> tide::Result {
+    let stages = parse_stages(&page.stage)?;
+    let all = shared
+        .sales
+        .read()
+        .map_err(|_| tide::Error::from_str(StatusCode::InternalServerError, "Sales are locked."))?;
+    let sales: Vec<&Sale> = all
+        .iter()
+        .rev()
+        .filter(|x| str_id.is_none_or(|id| x.id == id))
+        .filter(|x| page.owner.is_empty() || x.buyer == page.owner || x.seller == page.owner)
+        .filter(|x| x.time >= page.from && x.time <= page.to)
+        .filter(|x| x.rarity >= page.min_rarity && x.rarity <= page.max_rarity)
+        .filter(|x| stages.is_empty() || stages.contains(&x.stage))
+        .collect();
+    match calc_indexes(page, sales.len()) {
+        Some((start, end)) => {
+            let result = SalesResponse {
+                success: true,
+                data: &sales[start..end],
+                pagination: create_pagination(page, sales.len(), start, None),
+            };
+            Ok(serde_json::to_string(&result)
+                .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+                .into())
+        }
+        None => Ok(create_error(StatusCode::BadRequest, "Offset is too big.")),
+    }
+}
+// Found items in the order asked for, repeated ids once, empty ones
+// (a trailing comma) skipped. The limit counts the ids left.
+fn create_batch(
+    mut ids: Vec<String>,
+    page: &Page,
+    max_batch_size: usize,
+    app_state: &AppState,
+) -> tide::Result {
+    let mut seen = std::collections::HashSet::with_capacity(ids.len());
+    ids.retain(|x| !x.is_empty() && seen.insert(x.clone()));
+    if ids.len() > max_batch_size {
+        return Ok(create_error(
+            StatusCode::BadRequest,
+            &format!("Batch is limited to {} ids.", max_batch_size),
+        ));
+    }
+    let (found, missing): (Vec<String>, Vec<String>) = ids
+        .into_iter()
+        .partition(|x| app_state.main_state.token_stage.contains_key(x));
+    let result = BatchResponse {
+        success: true,
+        data: collect_items(&found, page, app_state)?,
+        missing,
+    };
+    Ok(serde_json::to_string(&result)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .into())
+}
+
 fn filter_n_sort(
     in_tokens: &[String],
     is_priced: bool,
@@ -71,62 +833,320 @@ fn filter_n_sort(
     app_state: &AppState,
 ) -> Result<Vec<String>, tide::Error> {
     let mut tokens = Vec::with_capacity(in_tokens.len());
-    // if we have some filters
-    if page.stage != u8::MAX || page.start_price != 0 || page.end_price != u64::MAX {
+    if page.has_filters() {
+        let stages = parse_stages(&page.stage)?;
+        let name = page.name.to_lowercase();
+        let ms = &app_state.main_state;
         for str_id in in_tokens {
-            let price = if is_priced {
-                get_price(str_id, prices)?
-            } else {
-                42
-            };
-
-            let stage = get_element(&app_state.main_state.token_stage, str_id)?
-                .parse::<u8>()
-                .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
-            if (!is_priced
-                || price >= u128::from(page.start_price) && price <= u128::from(page.end_price))
-                && (page.stage == u8::MAX || page.stage == stage)
+            if is_priced {
+                let price = get_price(str_id, prices)?;
+                if price < u128::from(page.start_price) || price > u128::from(page.end_price) {
+                    continue;
+                }
+            }
+            if !stages.is_empty() {
+                let stage = get_element(&ms.token_stage, str_id)?
+                    .parse::<u8>()
+                    .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+                if !stages.contains(&stage) {
+                    continue;
+                }
+            }
+            if !name.is_empty() {
+                match app_state.id_name.get(str_id) {
+                    Some(x) if x.to_lowercase().contains(&name) => {}
+                    _ => continue,
+                }
+            }
+            let rarity = *get_element(&app_state.all_id_rarity, str_id)?;
+            let strength = *get_element(&app_state.all_id_strength, str_id)?;
+            if rarity < page.min_rarity
+                || rarity > page.max_rarity
+                || strength < page.min_strength
+                || strength > page.max_strength
             {
-                tokens.push(str_id.clone());
+                continue;
             }
+            if !page.traits.is_empty() {
+                let genes = decode_image(get_element(&ms.token_gen_image, str_id)?)
+                    .ok_or_else(internal_error)?;
+                if !page.traits.iter().all(|x| trait_matches(x, &genes)) {
+                    continue;
+                }
+            }
+            tokens.push(str_id.clone());
         }
     } else {
         tokens = in_tokens.to_owned();
     }
-    match page.sort {
-        1 => {
-            let some_cmp = |a: &String, b: &String| {
-                app_state
-                    .all_id_rarity
-                    .get(b)
-                    .unwrap_or(&0)
-                    .cmp(app_state.all_id_rarity.get(a).unwrap_or(&0))
-            };
-            tokens.sort_unstable_by(some_cmp);
+    if page.sort_by.is_empty() {
+        return Ok(tokens);
+    }
+    let mut keyed = Vec::with_capacity(tokens.len());
+    for str_id in tokens {
+        let values = sort_values(
+            &str_id,
+            &page.sort_by,
+            is_priced.then_some(prices),
+            app_state,
+        )?;
+        keyed.push((values, str_id));
+    }
+    // stable, equal items keep the id order of the list
+    keyed.sort_by(|a, b| cmp_sort_values(&page.sort_by, &a.0, &b.0));
+    Ok(keyed.into_iter().map(|x| x.1).collect())
+}
+fn cmp_sort_values(fields: &[SortField], a: &[SortValue], b: &[SortValue]) -> Ordering {
+    for (field, (a, b)) in fields.iter().zip(a.iter().zip(b)) {
+        let ord = if field.desc { b.cmp(a) } else { a.cmp(b) };
+        if ord != Ordering::Equal {
+            return ord;
         }
-        2 => {
-            let some_cmp = |a: &String, b: &String| {
-                app_state
-                    .all_id_strength
-                    .get(b)
-                    .unwrap_or(&0)
-                    .cmp(app_state.all_id_strength.get(a).unwrap_or(&0))
-            };
-            tokens.sort_unstable_by(some_cmp);
+    }
+    Ordering::Equal
+}
+// values of the sort keys of a dragon, in the order of the keys
+fn sort_values(
+    str_id: &str,
+    fields: &[SortField],
+    prices: Option<&HashMap<String, String>>,
+    app_s: &AppState,
+) -> Result<Vec<SortValue>, tide::Error> {
+    let to_int = |x: &str| {
+        x.parse::<u128>()
+            .map(SortValue::Int)
+            .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))
+    };
+    let mut values = Vec::with_capacity(fields.len());
+    for field in fields {
+        let (wins, loses) = get_element_or_zero(&app_s.all_id_fights, str_id);
+        let value = match field.key {
+            SortKey::Id => to_int(str_id)?,
+            SortKey::Rarity => SortValue::Int((*get_element(&app_s.all_id_rarity, str_id)?).into()),
+            SortKey::Strength => {
+                SortValue::Int((*get_element(&app_s.all_id_strength, str_id)?).into())
+            }
+            SortKey::Attack => SortValue::Int(get_element(&app_s.all_id_combat, str_id)?.0.into()),
+            SortKey::Defence => SortValue::Int(get_element(&app_s.all_id_combat, str_id)?.1.into()),
+            SortKey::Price => match prices {
+                Some(prices) => SortValue::Int(get_price(str_id, prices)?),
+                None => SortValue::Int(0),
+            },
+            SortKey::Stage => to_int(get_element(&app_s.main_state.token_stage, str_id)?)?,
+            SortKey::Name => {
+                SortValue::Text(app_s.id_name.get(str_id).cloned().unwrap_or_default())
+            }
+            SortKey::Wins => SortValue::Int(wins.into()),
+            // parts per million
+            SortKey::WinRatio => SortValue::Int(match wins + loses {
+                0 => 0,
+                fights => u128::from(wins) * 1_000_000 / u128::from(fights),
+            }),
+        };
+        values.push(value);
+    }
+    Ok(values)
+}
+// "-rarity,price" -> [rarity desc, price asc, id asc]
+fn parse_sort(sort: &str) -> Result<Vec<SortField>, tide::Error> {
+    let legacy = match sort.trim() {
+        "" | "0" => "",
+        "1" => "-rarity",
+        "2" => "-strength",
+        "3" => "price",
+        "4" => "-attack",
+        "5" => "-defence",
+        other => other,
+    };
+    let mut fields = Vec::new();
+    for item in legacy
+        .split(',')
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+    {
+        let (name, desc) = match item.strip_prefix('-') {
+            Some(name) => (name, true),
+            None => (item.strip_prefix('+').unwrap_or(item), false),
+        };
+        let key = match name {
+            "id" => SortKey::Id,
+            "rarity" => SortKey::Rarity,
+            "strength" => SortKey::Strength,
+            "attack" => SortKey::Attack,
+            "defence" => SortKey::Defence,
+            "price" => SortKey::Price,
+            "stage" => SortKey::Stage,
+            "name" => SortKey::Name,
+            "wins" => SortKey::Wins,
+            "win_ratio" => SortKey::WinRatio,
+            _ => {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    format!("Unknown sort key {}.", name),
+                ))
+            }
+        };
+        fields.push(SortField { key, desc });
+    }
+    // plain id order is the order of the lists already
+    if fields.iter().all(|x| x.key == SortKey::Id && !x.desc) {
+        return Ok(Vec::new());
+    }
+    if !fields.iter().any(|x| x.key == SortKey::Id) {
+        fields.push(SortField {
+            key: SortKey::Id,
+            desc: false,
+        });
+    }
+    Ok(fields)
+}
+// Page with the trait filters of the query, a section name filters by
+// the type and <section>_color by the color
+fn parse_page(req: &Request<SharedState>) -> Result<Page, tide::Error> {
+    let mut page: Page = req.query()?;
+    // pages are counted by it, every list needs it checked
+    if page.limit == 0 {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "Limit cannot be zero.",
+        ));
+    }
+    if !page.owner.is_empty() {
+        page.owner = normalize(&page.owner).map_err(|e| {
+            tide::Error::from_str(StatusCode::BadRequest, format!("Bad owner: {}.", e))
+        })?;
+    }
+    page.sort_by = parse_sort(&page.sort)?;
+    if !page.cursor.is_empty() {
+        page.after = Some(decode_cursor(&page.cursor, &page)?);
+    }
+    for (key, value) in req.url().query_pairs() {
+        let (name, color) = match key.strip_suffix("_color") {
+            Some(name) => (name, true),
+            None => (key.as_ref(), false),
+        };
+        if let Some(section) = SECTION_NAMES.iter().position(|x| *x == name) {
+            if color && section >= COLORED_SECTIONS {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    format!("Bad {} filter.", key),
+                ));
+            }
+            let value = value.parse().map_err(|_| {
+                tide::Error::from_str(StatusCode::BadRequest, format!("Bad {} filter.", key))
+            })?;
+            page.traits.push(TraitFilter {
+                section,
+                color,
+                value,
+            });
         }
-        3 => {
-            if is_priced {
-                let some_cmp = |a: &String, b: &String| {
-                    get_price(a, prices)
-                        .unwrap_or(0)
-                        .cmp(&get_price(b, prices).unwrap_or(0))
-                };
-                tokens.sort_unstable_by(some_cmp);
+    }
+    Ok(page)
+}
+// The sales endpoints answer from the recorded sales, not from a state, and
+// take from/to instead: an archived state can't be asked for
+fn reject_at(req: &Request<SharedState>) -> Result<(), tide::Error> {
+    let query: AtQuery = req.query()?;
+    if query.at_block.is_some() || query.at_time.is_some() {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "Sales take from and to, not at_block or at_time.",
+        ));
+    }
+    Ok(())
+}
+
+// The live state, or the last archived one at ?at_block= or ?at_time=
+async fn load_state(req: &Request<SharedState>) -> Result<Arc<AppState>, tide::Error> {
+    let query: AtQuery = req.query()?;
+    let shared = req.state();
+    let at = match (query.at_block, query.at_time) {
+        (None, None) => return Ok(shared.app_state.load_full()),
+        (Some(block), None) => At::Block(
+            block
+                .parse()
+                .map_err(|_| tide::Error::from_str(StatusCode::BadRequest, "Bad at_block."))?,
+        ),
+        (None, Some(time)) => At::Time(time),
+        (Some(_), Some(_)) => {
+            return Err(tide::Error::from_str(
+                StatusCode::BadRequest,
+                "Either at_block or at_time, not both.",
+            ))
+        }
+    };
+    let checkpoint = match archive::lock(shared).resolve(at) {
+        Some(Found::State(state)) => return Ok(state),
+        Some(Found::Stored(checkpoint)) => checkpoint,
+        None => {
+            return Err(tide::Error::from_str(
+                StatusCode::NotFound,
+                "Nothing is archived that far back.",
+            ))
+        }
+    };
+    let dir = shared.config.archive_path.clone();
+    let state = async_std::task::spawn_blocking(move || archive::read(&dir, checkpoint))
+        .await
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?
+        .ok_or_else(|| {
+            tide::Error::from_str(
+                StatusCode::NotFound,
+                format!("Block {} is archived by another version.", checkpoint.block),
+            )
+        })?;
+    let state = Arc::new(state);
+    archive::lock(shared).cached = Some((checkpoint, Arc::clone(&state)));
+    Ok(state)
+}
+fn parse_event_filter(req: &Request<SharedState>) -> Result<EventFilter, tide::Error> {
+    let query: EventsQuery = req.query()?;
+    let bad_request = |e: String| tide::Error::from_str(StatusCode::BadRequest, e);
+    let mut filter = EventFilter::default();
+    if !query.owner.is_empty() {
+        filter.owner =
+            normalize(&query.owner).map_err(|e| bad_request(format!("Bad owner: {}.", e)))?;
+    }
+    let split = |text: &str| -> Vec<String> {
+        text.split(',')
+            .map(|x| x.trim().to_string())
+            .filter(|x| !x.is_empty())
+            .collect()
+    };
+    filter.ids = split(&query.id);
+    for kind in split(&query.kind) {
+        filter.kinds.push(
+            kind.parse()
+                .map_err(|e| bad_request(format!("Bad kind: {}.", e)))?,
+        );
+    }
+    Ok(filter)
+}
+// "0,1" -> [0, 1], 255 is any stage as before
+fn parse_stages(stage: &str) -> Result<Vec<u8>, tide::Error> {
+    let mut stages = Vec::new();
+    for x in stage.split(',').filter(|x| !x.trim().is_empty()) {
+        match x.trim().parse::<u8>() {
+            Ok(u8::MAX) => return Ok(Vec::new()),
+            Ok(x) => stages.push(x),
+            Err(_) => {
+                return Err(tide::Error::from_str(
+                    StatusCode::BadRequest,
+                    format!("Bad stage {}.", x),
+                ))
             }
         }
-        _ => {}
     }
-    Ok(tokens)
+    Ok(stages)
+}
+fn trait_matches(filter: &TraitFilter, genes: &ImageGenes) -> bool {
+    let gene = genes.sections()[filter.section].1;
+    if filter.color {
+        gene.color.map(u16::from) == Some(filter.value)
+    } else {
+        gene.kind == filter.value
+    }
 }
 fn get_price(str_id: &str, h_m: &HashMap<String, String>) -> Result<u128, tide::Error> {
     h_m.get(str_id)
@@ -134,9 +1154,9 @@ fn get_price(str_id: &str, h_m: &HashMap<String, String>) -> Result<u128, tide::
         .parse::<u128>()
         .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))
 }
-fn get_priced_dragons(what: &Handler, req: &Request<Arc<Mutex<Box<AppState>>>>) -> tide::Result {
-    let app_state = &req.state().lock().unwrap();
-    let page: &Page = &req.query()?;
+async fn get_priced_dragons(what: &Handler, req: &Request<SharedState>) -> tide::Result {
+    let app_state = &load_state(req).await?;
+    let page = &parse_page(req)?;
     let prices = match what {
         Handler::Market => &app_state.market_id_price,
         Handler::Battle => &app_state.battle_id_price,
@@ -151,6 +1171,7 @@ fn get_priced_dragons(what: &Handler, req: &Request<Arc<Mutex<Box<AppState>>>>)
         create_dragons(
             &filter_n_sort(all_tokens, true, prices, page, app_state)?,
             page,
+            Some(prices),
             app_state,
         )
     } else {
@@ -161,39 +1182,171 @@ fn get_priced_dragons(what: &Handler, req: &Request<Arc<Mutex<Box<AppState>>>>)
         };
         let tokens = match owned_id.get(&page.owner) {
             Some(result) => result,
-            None => return Ok(create_response(vec![], page, 0)?.into()),
+            None => {
+                return Ok(create_response(vec![], create_pagination(page, 0, 0, None))?.into())
+            }
         };
         create_dragons(
             &filter_n_sort(tokens, true, prices, page, app_state)?,
             page,
+            Some(prices),
             app_state,
         )
     }
 }
-fn create_dragons(tokens: &[String], page: &Page, app_state: &AppState) -> tide::Result {
-    if page.limit == 0 {
-        return Ok(create_error(
-            StatusCode::BadRequest,
-            "Limit cannot be zero.",
-        ));
+// "90" seconds, "15m", "4h", "1d", "1w"
+fn parse_interval(text: &str) -> Option<u64> {
+    let text = text.trim();
+    let (number, unit) = match text.char_indices().last()? {
+        (i, 'm') => (&text[..i], 60),
+        (i, 'h') => (&text[..i], 60 * 60),
+        (i, 'd') => (&text[..i], 24 * 60 * 60),
+        (i, 'w') => (&text[..i], 7 * 24 * 60 * 60),
+        _ => (text, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|x| x.checked_mul(unit))
+        .filter(|x| *x > 0)
+}
+// the lowest market price per key (rarity, stage), u8::MAX keeps the overall one
+fn market_floors<F>(app_s: &AppState, key_of: F) -> Result<BTreeMap<u8, u128>, tide::Error>
+where
+    F: Fn(&str) -> Result<u8, tide::Error>,
+{
+    let mut floors: BTreeMap<u8, u128> = BTreeMap::new();
+    for str_id in &app_s.market_id_list {
+        let price = get_price(str_id, &app_s.market_id_price)?;
+        for key in [key_of(str_id)?, u8::MAX] {
+            let floor = floors.entry(key).or_insert(price);
+            *floor = std::cmp::min(*floor, price);
+        }
     }
+    Ok(floors)
+}
+fn owner_listings<'a>(
+    owner: &str,
+    owned_id: &'a HashMap<String, Vec<String>>,
+    prices: &'a HashMap<String, String>,
+) -> Vec<Listing<'a>> {
+    let mut listings: Vec<Listing> = owned_id
+        .get(owner)
+        .into_iter()
+        .flatten()
+        .filter_map(|id| {
+            prices.get(id).map(|price| Listing {
+                id: id.as_str(),
+                price: price.as_str(),
+            })
+        })
+        .collect();
+    listings.sort_by_key(|x| x.id.parse::<u128>().unwrap_or(u128::MAX));
+    listings
+}
+fn create_dragons(
+    tokens: &[String],
+    page: &Page,
+    prices: Option<&HashMap<String, String>>,
+    app_state: &AppState,
+) -> tide::Result {
     let real_end = tokens.len();
-    match calc_indexes(page, real_end) {
+    let fields = page_fields(page);
+    let indexes = match &page.after {
+        Some(cursor) => {
+            let start = cursor_start(tokens, cursor, prices, app_state)?;
+            Some((start, std::cmp::min(start + page.limit, real_end)))
+        }
+        None => calc_indexes(page, real_end),
+    };
+    match indexes {
         Some((start, end)) => {
-            let items = collect_items(&tokens[start..end], app_state)?;
-            Ok(create_response(items, page, real_end)?.into())
+            let items = collect_items(&tokens[start..end], page, app_state)?;
+            let next_cursor = if !page.unkeyed && start < end && end < real_end {
+                let cursor = Cursor {
+                    keys: sort_values(&tokens[end - 1], &fields, prices, app_state)?,
+                    sort: fields,
+                };
+                Some(encode_cursor(&cursor)?)
+            } else {
+                None
+            };
+            let pagination = create_pagination(page, real_end, start, next_cursor);
+            Ok(create_response(items, pagination)?.into())
         }
         None => Ok(create_error(StatusCode::BadRequest, "Offset is too big.")),
     }
 }
+// the order of a listing, plain lists are in the id order
+fn page_fields(page: &Page) -> Vec<SortField> {
+    if page.sort_by.is_empty() {
+        return vec![SortField {
+            key: SortKey::Id,
+            desc: false,
+        }];
+    }
+    page.sort_by.clone()
+}
+// first item after the cursor, the list may have changed since it was made
+fn cursor_start(
+    tokens: &[String],
+    cursor: &Cursor,
+    prices: Option<&HashMap<String, String>>,
+    app_state: &AppState,
+) -> Result<usize, tide::Error> {
+    let (mut low, mut high) = (0, tokens.len());
+    while low < high {
+        let mid = (low + high) / 2;
+        let values = sort_values(&tokens[mid], &cursor.sort, prices, app_state)?;
+        if cmp_sort_values(&cursor.sort, &values, &cursor.keys) == Ordering::Greater {
+            high = mid;
+        } else {
+            low = mid + 1;
+        }
+    }
+    Ok(low)
+}
+fn encode_cursor(cursor: &Cursor) -> Result<String, tide::Error> {
+    let json = serde_json::to_vec(cursor)
+        .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?;
+    Ok(json.iter().map(|b| format!("{:02x}", b)).collect())
+}
+fn decode_cursor(text: &str, page: &Page) -> Result<Cursor, tide::Error> {
+    let bad_cursor = || tide::Error::from_str(StatusCode::BadRequest, "Bad cursor.");
+    if !text.is_ascii() || !text.len().is_multiple_of(2) {
+        return Err(bad_cursor());
+    }
+    let bytes = (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&text[i..i + 2], 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .map_err(|_| bad_cursor())?;
+    let cursor: Cursor = serde_json::from_slice(&bytes).map_err(|_| bad_cursor())?;
+    if cursor.sort != page_fields(page) || cursor.keys.len() != cursor.sort.len() {
+        return Err(tide::Error::from_str(
+            StatusCode::BadRequest,
+            "Cursor is for another sort.",
+        ));
+    }
+    Ok(cursor)
+}
 fn create_error(code: tide::StatusCode, err_text: &str) -> tide::Response {
     let mut response = Response::new(code);
-    response.set_body(format!(
-        "{{\"success\":false,\"error\":{{\"code\":{},\"message\":\"{}\"}}}}",
-        code, err_text
-    ));
+    response.set_body(json!({
+        "success": false,
+        "error": {"code": code as u16, "message": err_text},
+    }));
     response
 }
+// Client errors passed up with `?` (bad query values, bad cursors and such)
+// get the same body as the ones made by create_error.
+pub async fn json_errors(response: Response) -> tide::Result {
+    let status = response.status();
+    match response.error() {
+        Some(e) if status.is_client_error() => Ok(create_error(status, &e.to_string())),
+        _ => Ok(response),
+    }
+}
 fn calc_indexes(page: &Page, real_end: usize) -> Option<(usize, usize)> {
     let start = page.offset * page.limit;
     if real_end == 0 {
@@ -206,11 +1359,19 @@ fn calc_indexes(page: &Page, real_end: usize) -> Option<(usize, usize)> {
 }
 fn collect_items<'a>(
     tokens: &'a [String],
+    page: &Page,
     app_state: &'a AppState,
 ) -> Result<Vec<Item<'a>>, tide::Error> {
     let mut items = Vec::with_capacity(tokens.len());
     for str_id in tokens {
-        items.push(create_item(str_id, app_state)?);
+        let mut item = create_item(str_id, app_state)?;
+        if page.expands("genes") {
+            item.genes = Some(decode_image(item.gen_image).ok_or_else(internal_error)?);
+        }
+        if page.bech32 {
+            item.owner = Cow::Owned(to_bech32(&item.owner));
+        }
+        items.push(item);
     }
     Ok(items)
 }
@@ -223,7 +1384,7 @@ fn create_item<'a>(str_id: &'a str, app_s: &'a AppState) -> Result<Item<'a>, tid
     Ok(Item {
         id: str_id,
         name,
-        owner: get_element(&app_s.all_id_owner, str_id)?,
+        owner: Cow::Borrowed(get_element(&app_s.all_id_owner, str_id)?),
         url: get_element(&ms.token_uris, str_id)?,
         gen_image: get_element(&ms.token_gen_image, str_id)?,
         gen_fight: get_element(&ms.token_gen_battle, str_id)?,
@@ -231,21 +1392,104 @@ fn create_item<'a>(str_id: &'a str, app_s: &'a AppState) -> Result<Item<'a>, tid
             .parse()
             .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?,
         rarity: *get_element(&app_s.all_id_rarity, str_id)?,
+        strength: *get_element(&app_s.all_id_strength, str_id)?,
+        attack: get_element(&app_s.all_id_combat, str_id)?.0,
+        defence: get_element(&app_s.all_id_combat, str_id)?.1,
         // TODO Rewrite fights like the names
         fights_win: get_element_or_zero(&app_s.all_id_fights, str_id).0,
         fights_lose: get_element_or_zero(&app_s.all_id_fights, str_id).1,
         actions: collect_actions(str_id, app_s),
-        // TODO write true parents
-        parents: [].to_vec(),
-        // TODO write true children
-        children: [].to_vec(),
+        parents: match app_s.all_id_parents.get(str_id) {
+            Some((father, mother)) => vec![
+                create_short_item(father, app_s)?,
+                create_short_item(mother, app_s)?,
+            ],
+            None => vec![],
+        },
+        children: match app_s.all_id_children.get(str_id) {
+            Some(children) => children
+                .iter()
+                .map(|x| create_short_item(x, app_s))
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        },
         wounds: app_s
             .all_id_wounds
             .get(str_id)
             .unwrap_or(&[].to_vec())
             .clone(),
+        genes: None,
+    })
+}
+fn create_fighter<'a>(str_id: &'a str, app_s: &'a AppState) -> Result<Fighter<'a>, tide::Error> {
+    let gen_battle = get_element(&app_s.main_state.token_gen_battle, str_id)?;
+    Ok(Fighter {
+        id: str_id,
+        genes: decode_combat(gen_battle).ok_or_else(internal_error)?,
+        wounds: app_s
+            .all_id_wounds
+            .get(str_id)
+            .map_or(&[], |x| x.as_slice()),
     })
 }
+fn create_parent<'a>(str_id: &'a str, app_s: &AppState) -> Result<Parent<'a>, tide::Error> {
+    let ms = &app_s.main_state;
+    Ok(Parent {
+        id: str_id,
+        genes: decode_image(get_element(&ms.token_gen_image, str_id)?)
+            .ok_or_else(internal_error)?,
+        combat: decode_combat(get_element(&ms.token_gen_battle, str_id)?)
+            .ok_or_else(internal_error)?,
+    })
+}
+fn create_short_item(str_id: &str, app_s: &AppState) -> Result<ShortItem, tide::Error> {
+    Ok(ShortItem {
+        id: str_id
+            .parse()
+            .map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))?,
+        url: app_s
+            .main_state
+            .token_uris
+            .get(str_id)
+            .cloned()
+            .unwrap_or_default(),
+    })
+}
+// (ancestors, descendants) tells which way to walk from this node
+fn family_node(
+    str_id: &str,
+    depth: usize,
+    (ancestors, descendants): (bool, bool),
+    app_s: &AppState,
+) -> Result<FamilyNode, tide::Error> {
+    let short = create_short_item(str_id, app_s)?;
+    let mut node = FamilyNode {
+        id: short.id,
+        url: short.url,
+        parents: vec![],
+        children: vec![],
+    };
+    if depth == 0 {
+        return Ok(node);
+    }
+    if ancestors {
+        if let Some((father, mother)) = app_s.all_id_parents.get(str_id) {
+            node.parents = vec![
+                family_node(father, depth - 1, (true, false), app_s)?,
+                family_node(mother, depth - 1, (true, false), app_s)?,
+            ];
+        }
+    }
+    if descendants {
+        if let Some(children) = app_s.all_id_children.get(str_id) {
+            for child in children {
+                node.children
+                    .push(family_node(child, depth - 1, (false, true), app_s)?);
+            }
+        }
+    }
+    Ok(node)
+}
 fn collect_actions<'a>(str_id: &str, app_s: &'a AppState) -> Vec<(u8, &'a str)> {
     let mut result: Vec<(u8, &str)> = Vec::with_capacity(3);
     if let Some(x) = app_s.battle_id_price.get(str_id) {
@@ -268,17 +1512,25 @@ fn get_element<'a, T>(h_m: &'a HashMap<String, T>, str_id: &str) -> Result<&'a T
 fn get_element_or_zero(h_m: &HashMap<String, (u32, u32)>, str_id: &str) -> (u32, u32) {
     *h_m.get(str_id).unwrap_or(&(0, 0))
 }
-fn create_response(items: Vec<Item>, page: &Page, records: usize) -> Result<String, tide::Error> {
-    let cur_pag = Pagination {
+fn create_pagination(
+    page: &Page,
+    records: usize,
+    start: usize,
+    next_cursor: Option<String>,
+) -> Pagination {
+    Pagination {
         records,
-        pages: (records + page.limit - 1) / page.limit,
-        current_page: page.offset + 1,
+        pages: records.div_ceil(page.limit),
+        current_page: start / page.limit + 1,
         limit: page.limit,
-    };
+        next_cursor,
+    }
+}
+fn create_response(items: Vec<Item>, pagination: Pagination) -> Result<String, tide::Error> {
     let result = OkResponse {
         success: true,
         data: items,
-        pagination: cur_pag,
+        pagination,
     };
     serde_json::to_string(&result).map_err(|e| tide::Error::new(StatusCode::InternalServerError, e))
 }
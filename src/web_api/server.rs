@@ -0,0 +1,44 @@
+use crate::state::SharedState;
+use crate::web_api::routes::*;
+#[cfg(debug_assertions)]
+use tide::http::headers::HeaderValue;
+
+pub fn build_server(shared: SharedState) -> tide::Server<SharedState> {
+    let mut app = tide::with_state(shared);
+    #[cfg(debug_assertions)]
+    {
+        println!("Debug mode, CORS allow \"*\"");
+        let cors_debug = tide::security::CorsMiddleware::new()
+            .allow_methods("GET, POST, DELETE".parse::<HeaderValue>().unwrap())
+            .allow_origin(tide::security::Origin::from("*"))
+            .allow_credentials(false);
+        app.with(cors_debug);
+    }
+    app.with(tide::utils::After(json_errors));
+    app.at("/api/v1/dragons").get(get_dragons);
+    app.at("/api/v1/dragons/search").get(search_dragons);
+    app.at("/api/v1/dragons/batch").post(post_dragons_batch);
+    app.at("/api/v1/dragons/:id").get(get_dragon_by_id);
+    app.at("/api/v1/dragons/:id/family").get(get_dragon_family);
+    app.at("/api/v1/dragons/:id/genes").get(get_dragon_genes);
+    app.at("/api/v1/dragons/:id/combat").get(get_dragon_combat);
+    app.at("/api/v1/dragons/:id/history")
+        .get(get_dragon_history);
+    app.at("/api/v1/owners/:address").get(get_owner);
+    app.at("/api/v1/stats").get(get_stats);
+    app.at("/api/v1/archive").get(get_archive);
+    app.at("/api/v1/events").get(get_events);
+    app.at("/api/v1/events/ws").get(get_events_ws);
+    app.at("/api/v1/webhooks").post(post_webhook);
+    app.at("/api/v1/webhooks/:id")
+        .get(get_webhook)
+        .delete(delete_webhook);
+    app.at("/api/v1/market").get(get_from_market);
+    app.at("/api/v1/market/history").get(get_market_history);
+    app.at("/api/v1/market/analytics").get(get_market_analytics);
+    app.at("/api/v1/battle").get(get_from_battle);
+    app.at("/api/v1/battle/simulate").get(get_battle_simulation);
+    app.at("/api/v1/breed").get(get_from_breed);
+    app.at("/api/v1/breed/preview").get(get_breed_preview);
+    app
+}
@@ -1,13 +1,45 @@
+use crate::archive::Checkpoint;
+use crate::genes::{CombatGenes, ImageGenes};
+use crate::sales::{Candle, Sale, Volume};
+use crate::state::Stats;
+use crate::webhooks::WebhookFilter;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+
 #[derive(Deserialize)]
 #[serde(default)]
 pub struct Page {
     pub limit: usize,
     pub offset: usize,
     pub owner: String,
-    pub stage: u8,
-    pub sort: u8, // 0 and _ - id, 1 - rarity, 2 - strong, 3 - price,
+    pub ids: String,   // comma separated, a batch instead of a list
+    pub name: String,  // part of the name, any case
+    pub stage: String, // comma separated, e.g. "0,1"
+    pub min_rarity: u8,
+    pub max_rarity: u8,
+    pub min_strength: u16,
+    pub max_strength: u16,
+    // keys with "-" for descending, e.g. "-rarity,price", id breaks ties;
+    // the old 0 - id, 1 - rarity, 2 - strong, 3 - price, 4 - attack, 5 - defence
+    pub sort: String,
     pub start_price: u64,
     pub end_price: u64,
+    pub expand: String, // comma separated, "genes"
+    pub cursor: String, // next_cursor of the previous page, offset is ignored then
+    pub bech32: bool,   // owners as zil1...
+    pub from: u64,      // unix seconds, sales history only
+    pub to: u64,
+    // ?aura=3&wings_color=2, taken from the query by parse_page
+    #[serde(skip)]
+    pub traits: Vec<TraitFilter>,
+    // parsed sort, empty keeps the id order
+    #[serde(skip)]
+    pub sort_by: Vec<SortField>,
+    #[serde(skip)]
+    pub after: Option<Cursor>,
+    // the order can't be resumed from a cursor
+    #[serde(skip)]
+    pub unkeyed: bool,
 }
 impl Default for Page {
     fn default() -> Self {
@@ -15,13 +47,156 @@ impl Default for Page {
             limit: 6,
             offset: 0,
             owner: String::new(),
-            stage: 255,
-            sort: 0,
+            ids: String::new(),
+            name: String::new(),
+            stage: String::new(),
+            min_rarity: 0,
+            max_rarity: u8::MAX,
+            min_strength: 0,
+            max_strength: u16::MAX,
+            sort: String::new(),
             start_price: 0,
             end_price: u64::MAX,
+            expand: String::new(),
+            cursor: String::new(),
+            bech32: false,
+            from: 0,
+            to: u64::MAX,
+            traits: Vec::new(),
+            sort_by: Vec::new(),
+            after: None,
+            unkeyed: false,
         }
     }
 }
+impl Page {
+    pub fn expands(&self, what: &str) -> bool {
+        self.expand.split(',').any(|x| x.trim() == what)
+    }
+    pub fn has_filters(&self) -> bool {
+        !self.stage.is_empty()
+            || !self.name.is_empty()
+            || self.start_price != 0
+            || self.end_price != u64::MAX
+            || self.min_rarity != 0
+            || self.max_rarity != u8::MAX
+            || self.min_strength != 0
+            || self.max_strength != u16::MAX
+            || !self.traits.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SortKey {
+    Id,
+    Rarity,
+    Strength,
+    Attack,
+    Defence,
+    Price,
+    Stage,
+    Name,
+    Wins,
+    WinRatio,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SortField {
+    pub key: SortKey,
+    pub desc: bool,
+}
+
+// Value of a sort key for one dragon
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum SortValue {
+    Int(u128),
+    Text(String),
+}
+
+// Position after the last item of a page: the sort and its key values
+// (ending with the id). Sent hex encoded as next_cursor. No state version:
+// the next page starts after these keys in whatever state is current, so a
+// refresh in between neither repeats nor skips the dragons that stayed.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Cursor {
+    pub sort: Vec<SortField>,
+    pub keys: Vec<SortValue>,
+}
+
+// type (or color) of a gen_image section, see SECTION_NAMES
+pub struct TraitFilter {
+    pub section: usize,
+    pub color: bool,
+    pub value: u16,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SearchQuery {
+    pub q: String,
+}
+
+pub const MAX_FAMILY_DEPTH: usize = 10;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct FamilyQuery {
+    pub depth: usize, // generations up and down
+}
+impl Default for FamilyQuery {
+    fn default() -> Self {
+        Self { depth: 1 }
+    }
+}
+
+pub const MAX_CANDLES: usize = 1000;
+
+#[derive(Deserialize)]
+#[serde(default)]
+pub struct AnalyticsQuery {
+    pub interval: String, // of the candles, seconds or with m, h, d, w, e.g. "4h"
+    pub candles: usize,   // intervals back from the current one
+}
+impl Default for AnalyticsQuery {
+    fn default() -> Self {
+        Self {
+            interval: String::from("1d"),
+            candles: 30,
+        }
+    }
+}
+
+// without defender the attacker is matched against the battle waiting list
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct SimulateQuery {
+    pub attacker: String,
+    pub defender: String,
+}
+
+// without father the mother is matched against the breed waiting list
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct PreviewQuery {
+    pub father: String,
+    pub mother: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct EventsQuery {
+    pub owner: String,
+    pub id: String,   // comma separated
+    pub kind: String, // comma separated, e.g. "listed,transfer"
+}
+
+// the state of a past block (or moment) instead of the live one
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct AtQuery {
+    pub at_block: Option<String>, // u128, not parsed by the query decoder
+    pub at_time: Option<u64>,     // unix seconds
+}
 
 pub enum Handler {
     Market,
@@ -35,22 +210,37 @@ pub struct ShortItem {
     pub url: String,
 }
 
+#[derive(Serialize)]
+pub struct FamilyNode {
+    pub id: u64,
+    pub url: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<FamilyNode>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<FamilyNode>,
+}
+
 #[derive(Serialize, Clone)]
 pub struct Item<'a> {
     pub id: &'a str,
     pub name: &'a str,
-    pub owner: &'a str,
+    pub owner: Cow<'a, str>,
     pub url: &'a str,
     pub gen_image: &'a str,
     pub gen_fight: &'a str,
     pub stage: u8,
     pub rarity: u8,
+    pub strength: u16,
+    pub attack: u16,
+    pub defence: u16,
     pub fights_win: u32,
     pub fights_lose: u32,
     pub actions: Vec<(u8, &'a str)>,
     pub parents: Vec<ShortItem>,
     pub children: Vec<ShortItem>,
     pub wounds: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub genes: Option<ImageGenes>,
 }
 
 #[derive(Serialize)]
@@ -59,6 +249,8 @@ pub struct Pagination {
     pub pages: usize,
     pub current_page: usize,
     pub limit: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
 }
 
 #[derive(Serialize)]
@@ -67,3 +259,171 @@ pub struct OkResponse<'a> {
     pub data: Vec<Item<'a>>,
     pub pagination: Pagination,
 }
+
+#[derive(Serialize)]
+pub struct BatchResponse<'a> {
+    pub success: bool,
+    pub data: Vec<Item<'a>>,
+    pub missing: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct FamilyResponse {
+    pub success: bool,
+    pub data: FamilyNode,
+}
+
+#[derive(Serialize)]
+pub struct GenesResponse {
+    pub success: bool,
+    pub data: ImageGenes,
+}
+
+// What FightPlace resolves a fight from, wounds as in wounded_list.
+#[derive(Serialize)]
+pub struct Fighter<'a> {
+    pub id: &'a str,
+    #[serde(flatten)]
+    pub genes: CombatGenes,
+    pub wounds: &'a [String],
+}
+
+#[derive(Serialize)]
+pub struct Matchup<'a> {
+    pub attacker: Fighter<'a>,
+    pub defender: Fighter<'a>,
+}
+
+#[derive(Serialize)]
+pub struct SimulateResponse<'a> {
+    pub success: bool,
+    pub data: Vec<Matchup<'a>>,
+}
+
+// What BreedPlace mixes the genes of the child from.
+#[derive(Serialize)]
+pub struct Parent<'a> {
+    pub id: &'a str,
+    pub genes: ImageGenes,
+    pub combat: CombatGenes,
+}
+
+#[derive(Serialize)]
+pub struct Pairing<'a> {
+    pub father: Parent<'a>,
+    pub mother: Parent<'a>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub price: Option<&'a str>, // of the father on the waiting list
+}
+
+#[derive(Serialize)]
+pub struct PreviewResponse<'a> {
+    pub success: bool,
+    pub data: Vec<Pairing<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct CombatResponse {
+    pub success: bool,
+    pub data: CombatGenes,
+}
+
+#[derive(Serialize)]
+pub struct Listing<'a> {
+    pub id: &'a str,
+    pub price: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct OwnerProfile<'a> {
+    pub address: String,
+    pub dragons: usize,
+    pub stages: BTreeMap<u8, usize>,   // stage -> count
+    pub rarities: BTreeMap<u8, usize>, // rarity -> count
+    pub market: Vec<Listing<'a>>,
+    pub battle: Vec<Listing<'a>>,
+    pub breed: Vec<Listing<'a>>,
+    pub fights_win: u32,
+    pub fights_lose: u32,
+    pub portfolio_value: String, // by the market floor prices
+}
+
+#[derive(Serialize)]
+pub struct OwnerResponse<'a> {
+    pub success: bool,
+    pub data: OwnerProfile<'a>,
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse<'a> {
+    pub success: bool,
+    pub data: &'a Stats,
+}
+
+#[derive(Serialize)]
+pub struct ArchiveResponse<'a> {
+    pub success: bool,
+    pub data: &'a [Checkpoint],
+}
+
+#[derive(Deserialize)]
+pub struct WebhookRequest {
+    pub url: String,
+    pub secret: String, // HMAC-SHA256 key of the payload signatures
+    #[serde(flatten)]
+    pub filter: WebhookFilter,
+}
+
+// a registration without its secret
+#[derive(Serialize)]
+pub struct WebhookInfo<'a> {
+    pub id: &'a str,
+    pub url: &'a str,
+    #[serde(flatten)]
+    pub filter: &'a WebhookFilter,
+}
+
+#[derive(Serialize)]
+pub struct WebhookResponse<'a> {
+    pub success: bool,
+    pub data: WebhookInfo<'a>,
+}
+
+#[derive(Serialize)]
+pub struct SalesResponse<'a> {
+    pub success: bool,
+    pub data: &'a [&'a Sale],
+    pub pagination: Pagination,
+}
+
+// lowest current market prices, there are no keys for what isn't on sale
+#[derive(Serialize)]
+pub struct Floors {
+    pub overall: Option<String>,
+    pub by_stage: BTreeMap<u8, String>,
+    pub by_rarity: BTreeMap<u8, String>,
+}
+
+#[derive(Serialize)]
+pub struct Volumes {
+    #[serde(rename = "24h")]
+    pub day: Volume,
+    #[serde(rename = "7d")]
+    pub week: Volume,
+    #[serde(rename = "30d")]
+    pub month: Volume,
+}
+
+#[derive(Serialize)]
+pub struct Analytics {
+    pub floor: Floors,
+    pub volume: Volumes,
+    pub interval: u64, // seconds
+    pub candles: Vec<Candle>,
+}
+
+#[derive(Serialize)]
+pub struct AnalyticsResponse {
+    pub success: bool,
+    pub data: Analytics,
+}
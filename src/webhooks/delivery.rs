@@ -0,0 +1,202 @@
+use crate::events::ChangeEvent;
+use crate::state::SharedState;
+use crate::webhooks::target::{ip_host_is_public, PublicResolver};
+use crate::webhooks::{save, Delivery, Payload, Webhook, WebhookStore};
+use futures_util::{stream, StreamExt};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+use tokio::sync::broadcast::Receiver;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+
+// retries are never put off longer than this
+const MAX_RETRY_SECS: u64 = 3600;
+// payloads sent at the same time
+const MAX_CONCURRENT_DELIVERIES: usize = 16;
+
+// one save of the store at a time, in the order it was copied
+static SAVING: Mutex<()> = Mutex::new(());
+
+// Queues the matching events and delivers the due payloads once a second,
+// in a task of its own: a slow webhook never holds the events up.
+pub async fn run(shared: SharedState, mut events: Receiver<ChangeEvent>) {
+    let config = &shared.config;
+    // a redirect could lead anywhere, it counts as a failed delivery
+    let mut builder = reqwest::Client::builder()
+        .timeout(Duration::from_secs(config.webhook_timeout_secs))
+        .redirect(reqwest::redirect::Policy::none());
+    if !config.webhook_allow_private {
+        builder = builder.dns_resolver(Arc::new(PublicResolver));
+    }
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Webhooks are off, cannot build the client: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(deliver(Arc::clone(&shared), client));
+    loop {
+        let mut queued = match events.recv().await {
+            Ok(event) => lock(&shared).enqueue(&event, now()),
+            Err(RecvError::Lagged(missed)) => {
+                eprintln!("Webhooks missed {} events", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => return,
+        };
+        // the rest of the refresh is queued at once and saved once
+        loop {
+            match events.try_recv() {
+                Ok(event) => queued += lock(&shared).enqueue(&event, now()),
+                Err(TryRecvError::Lagged(missed)) => {
+                    eprintln!("Webhooks missed {} events", missed)
+                }
+                Err(TryRecvError::Empty) | Err(TryRecvError::Closed) => break,
+            }
+        }
+        if queued > 0 {
+            persist(&shared);
+        }
+    }
+}
+
+async fn deliver(shared: SharedState, client: reqwest::Client) {
+    let mut tick = interval(Duration::from_secs(1));
+    // a long round isn't followed by a burst of the missed ones
+    tick.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    loop {
+        tick.tick().await;
+        deliver_due(&shared, &client).await;
+    }
+}
+
+async fn deliver_due(shared: &SharedState, client: &reqwest::Client) {
+    let config = &shared.config;
+    let now = now();
+    let due: Vec<(Delivery, Option<Webhook>)> = {
+        let store = lock(shared);
+        store
+            .queue
+            .iter()
+            .filter(|x| x.next_at <= now)
+            .map(|x| (x.clone(), store.get(&x.webhook).cloned()))
+            .collect()
+    };
+    if due.is_empty() {
+        return;
+    }
+    let results: Vec<(u64, bool)> = stream::iter(due)
+        .map(|(delivery, hook)| async move {
+            let delivered = match hook {
+                // registered before the address checks, never called
+                Some(hook) if !config.webhook_allow_private && !ip_host_is_public(&hook.url) => {
+                    false
+                }
+                Some(hook) => send(client, &hook, &delivery.event).await,
+                // removed meanwhile
+                None => true,
+            };
+            (delivery.seq, delivered)
+        })
+        .buffer_unordered(MAX_CONCURRENT_DELIVERIES)
+        .collect()
+        .await;
+    {
+        let mut store = lock(shared);
+        for (seq, delivered) in results {
+            let index = match store.queue.iter().position(|x| x.seq == seq) {
+                Some(index) => index,
+                None => continue,
+            };
+            if delivered {
+                store.queue.remove(index);
+                continue;
+            }
+            let delivery = &mut store.queue[index];
+            delivery.attempts += 1;
+            if delivery.attempts >= config.webhook_max_attempts {
+                eprintln!(
+                    "Webhook {} gave up on {} {} after {} attempts",
+                    delivery.webhook,
+                    delivery.event.kind.as_str(),
+                    delivery.event.id,
+                    delivery.attempts
+                );
+                store.queue.remove(index);
+                continue;
+            }
+            let shift = std::cmp::min(delivery.attempts - 1, 16);
+            let delay = std::cmp::min(config.webhook_retry_secs << shift, MAX_RETRY_SECS);
+            delivery.next_at = now + delay;
+        }
+    }
+    persist(shared);
+}
+
+// any 2xx answer is a delivery
+async fn send(client: &reqwest::Client, hook: &Webhook, event: &ChangeEvent) -> bool {
+    let payload = Payload {
+        webhook: &hook.id,
+        event,
+    };
+    let body = match serde_json::to_string(&payload) {
+        Ok(body) => body,
+        Err(_) => return true,
+    };
+    let response = client
+        .post(&hook.url)
+        .header("content-type", "application/json")
+        .header("x-dragon-webhook", hook.id.as_str())
+        .header("x-dragon-signature", sign(&hook.secret, &body))
+        .body(body)
+        .send()
+        .await;
+    match response {
+        Ok(response) => response.status().is_success(),
+        Err(_) => false,
+    }
+}
+
+// "sha256=" and the hex HMAC-SHA256 of the body
+pub fn sign(secret: &str, body: &str) -> String {
+    let mut mac = match Hmac::<Sha256>::new_from_slice(secret.as_bytes()) {
+        Ok(mac) => mac,
+        Err(_) => return String::new(),
+    };
+    mac.update(body.as_bytes());
+    let mut result = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        result.push_str(&format!("{:02x}", byte));
+    }
+    result
+}
+
+pub(crate) fn lock(shared: &SharedState) -> std::sync::MutexGuard<'_, WebhookStore> {
+    // a panic elsewhere doesn't make the store unusable
+    shared
+        .webhooks
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+// Saves a copy, the store is not locked while the file is written.
+pub(crate) fn persist(shared: &SharedState) {
+    let path = &shared.config.webhooks_path;
+    if path.is_empty() {
+        return;
+    }
+    let _saving = SAVING
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    let store = lock(shared).clone();
+    if let Err(e) = save(path, &store) {
+        eprintln!("Cannot save webhooks {}: {}", path, e);
+    }
+}
+pub(crate) fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
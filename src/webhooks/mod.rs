@@ -0,0 +1,8 @@
+pub mod structs;
+pub use structs::*;
+pub mod store;
+pub use store::{load, save};
+pub mod delivery;
+pub use delivery::{run, sign};
+pub mod target;
+pub use target::{check_url, is_public};
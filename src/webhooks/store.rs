@@ -0,0 +1,26 @@
+use crate::webhooks::WebhookStore;
+use std::fs::{self, File};
+use std::io::{BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+// Written to a temp file and renamed like the snapshot.
+pub fn save(path: &str, store: &WebhookStore) -> Result<(), Error> {
+    if path.is_empty() {
+        return Ok(());
+    }
+    let tmp_path = format!("{}.tmp", path);
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    serde_json::to_writer(&mut writer, store).map_err(Error::other)?;
+    writer.flush()?;
+    drop(writer);
+    fs::rename(&tmp_path, path)
+}
+
+// An empty store if there is no file yet.
+pub fn load(path: &str) -> Result<WebhookStore, Error> {
+    if path.is_empty() || !Path::new(path).exists() {
+        return Ok(WebhookStore::default());
+    }
+    let text = fs::read_to_string(path)?;
+    serde_json::from_str(&text).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+}
@@ -0,0 +1,135 @@
+use crate::events::{ChangeEvent, EventFilter, EventKind, ListKind};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+
+pub const MIN_SECRET_LEN: usize = 16;
+
+// What a webhook is called for, empty matches everything. With max_price,
+// listed and price_changed events match for the market only, at or under it.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct WebhookFilter {
+    pub owner: String,
+    pub ids: Vec<String>,
+    pub kinds: Vec<EventKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_price: Option<String>,
+}
+
+impl WebhookFilter {
+    pub fn matches(&self, event: &ChangeEvent) -> bool {
+        let filter = EventFilter {
+            owner: self.owner.clone(),
+            ids: self.ids.clone(),
+            kinds: self.kinds.clone(),
+        };
+        if !filter.matches(event) {
+            return false;
+        }
+        let priced = matches!(event.kind, EventKind::Listed | EventKind::PriceChanged);
+        if self.max_price.is_some() && priced && event.list != Some(ListKind::Market) {
+            return false;
+        }
+        match (&self.max_price, &event.to) {
+            (Some(max_price), Some(price)) if priced => {
+                match (max_price.parse::<u128>(), price.parse::<u128>()) {
+                    (Ok(max_price), Ok(price)) => price <= max_price,
+                    _ => false,
+                }
+            }
+            _ => true,
+        }
+    }
+}
+
+// A registered callback, the secret signs its payloads.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+    pub id: String,
+    pub url: String,
+    pub secret: String,
+    pub filter: WebhookFilter,
+}
+
+// One event on its way to one webhook.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Delivery {
+    pub seq: u64,
+    pub webhook: String,
+    pub event: ChangeEvent,
+    pub attempts: u32,
+    pub next_at: u64, // unix seconds
+}
+
+// The body POSTed to a webhook, signed in the X-Dragon-Signature header.
+#[derive(Serialize)]
+pub struct Payload<'a> {
+    pub webhook: &'a str,
+    pub event: &'a ChangeEvent,
+}
+
+// Registrations and the delivery queue, saved after every change.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WebhookStore {
+    pub hooks: Vec<Webhook>,
+    pub queue: VecDeque<Delivery>,
+    pub next_seq: u64,
+}
+
+impl WebhookStore {
+    // the id is hard to guess, it is all it takes to delete the webhook
+    pub fn add(
+        &mut self,
+        url: String,
+        secret: String,
+        filter: WebhookFilter,
+        nonce: u128,
+    ) -> &Webhook {
+        let mut hasher = Sha256::new();
+        for part in [
+            url.as_bytes(),
+            secret.as_bytes(),
+            &self.next_seq.to_be_bytes(),
+            &nonce.to_be_bytes(),
+        ] {
+            hasher.update(part);
+        }
+        let id = hasher.finalize()[..16]
+            .iter()
+            .map(|x| format!("{:02x}", x))
+            .collect();
+        self.next_seq += 1;
+        self.hooks.push(Webhook {
+            id,
+            url,
+            secret,
+            filter,
+        });
+        &self.hooks[self.hooks.len() - 1]
+    }
+    pub fn get(&self, id: &str) -> Option<&Webhook> {
+        self.hooks.iter().find(|x| x.id == id)
+    }
+    // drops its undelivered payloads too
+    pub fn remove(&mut self, id: &str) -> bool {
+        let len = self.hooks.len();
+        self.hooks.retain(|x| x.id != id);
+        self.queue.retain(|x| x.webhook != id);
+        self.hooks.len() != len
+    }
+    pub fn enqueue(&mut self, event: &ChangeEvent, now: u64) -> usize {
+        let mut count = 0;
+        for hook in self.hooks.iter().filter(|x| x.filter.matches(event)) {
+            self.queue.push_back(Delivery {
+                seq: self.next_seq,
+                webhook: hook.id.clone(),
+                event: event.clone(),
+                attempts: 0,
+                next_at: now,
+            });
+            self.next_seq += 1;
+            count += 1;
+        }
+        count
+    }
+}
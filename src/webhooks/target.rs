@@ -0,0 +1,85 @@
+use hyper::client::connect::dns::Name;
+use reqwest::dns::{Addrs, Resolve, Resolving};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+
+// Anyone can register a webhook, so it may only point at public addresses:
+// not at this server, the local network or the cloud metadata service.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || (a == 100 && b & 0xc0 == 64)) // carrier-grade NAT
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || first & 0xfe00 == 0xfc00 // unique local
+                    || first & 0xffc0 == 0xfe80) // link-local
+            }
+        },
+    }
+}
+
+// Addresses of the host, an error if any of them isn't public. Blocking.
+pub fn resolve_public(host: &str, port: u16) -> Result<Vec<SocketAddr>, String> {
+    let addrs: Vec<SocketAddr> = match host.parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => (host, port)
+            .to_socket_addrs()
+            .map_err(|e| format!("{}: {}", host, e))?
+            .collect(),
+    };
+    if addrs.is_empty() {
+        return Err(format!("{} has no address", host));
+    }
+    match addrs.iter().find(|x| !is_public(x.ip())) {
+        Some(addr) => Err(format!("{} is not a public address", addr.ip())),
+        None => Ok(addrs),
+    }
+}
+
+// The host of a webhook url, checked on registration. Names are checked
+// again by PublicResolver on every delivery, they may resolve differently.
+pub fn check_url(url: &str) -> Result<(), String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    let host = url.host_str().ok_or("no host")?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    resolve_public(host, url.port_or_known_default().unwrap_or(80)).map(|_| ())
+}
+
+// False for an url with a private ip for host. Names are left to
+// PublicResolver, the client doesn't resolve ip hosts.
+pub fn ip_host_is_public(url: &str) -> bool {
+    let url = match reqwest::Url::parse(url) {
+        Ok(url) => url,
+        Err(_) => return true,
+    };
+    let host = url.host_str().unwrap_or_default();
+    match host.trim_start_matches('[').trim_end_matches(']').parse() {
+        Ok(ip) => is_public(ip),
+        Err(_) => true,
+    }
+}
+
+// DNS of the delivery client, private answers fail the request.
+pub struct PublicResolver;
+
+impl Resolve for PublicResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs = tokio::task::spawn_blocking(move || resolve_public(&host, 0)).await??;
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
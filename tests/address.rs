@@ -0,0 +1,37 @@
+use dragon_api::address::{normalize, to_bech32, Address, AddressError};
+
+const HEX: &str = "0x4baf5fada8e5db92c3d3242618c5b47133ae003c";
+const CHECKSUM: &str = "0x4BAF5faDA8e5Db92C3d3242618c5B47133AE003C";
+const BECH32: &str = "zil1fwh4ltdguhde9s7nysnp33d5wye6uqpugufkz7";
+
+#[test]
+fn formats_round_trip() {
+    let address = Address::parse(BECH32).unwrap();
+    assert_eq!(address.to_hex(), HEX);
+    assert_eq!(address.to_checksum(), CHECKSUM);
+    assert_eq!(address.to_bech32(), BECH32);
+    assert_eq!(to_bech32(HEX), BECH32);
+    for text in [
+        HEX,
+        CHECKSUM,
+        BECH32,
+        &HEX.to_uppercase()[2..],
+        " ZIL1FWH4LTDGUHDE9S7NYSNP33D5WYE6UQPUGUFKZ7",
+    ] {
+        assert_eq!(normalize(text).unwrap(), HEX, "{}", text);
+    }
+}
+
+#[test]
+fn invalid_addresses() {
+    assert_eq!(normalize("0xdead"), Err(AddressError::Format));
+    assert_eq!(normalize("ab€cd"), Err(AddressError::Format));
+    assert_eq!(normalize("€"), Err(AddressError::Format));
+    assert_eq!(normalize(&HEX.replace('4', "g")), Err(AddressError::Format));
+    let bad_checksum = CHECKSUM.replace("4BAF", "4baF");
+    assert_eq!(normalize(&bad_checksum), Err(AddressError::Checksum));
+    assert!(matches!(
+        normalize("zil1fwh4ltdguhde9s7nysnp33d5wye6uqpugufkz8"),
+        Err(AddressError::Bech32(_))
+    ));
+}
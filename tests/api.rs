@@ -0,0 +1,764 @@
+mod common;
+
+use common::*;
+use dragon_api::address::to_bech32;
+use dragon_api::sales::Sale;
+use dragon_api::state::{get_block_num, Shared};
+use dragon_api::web_api::build_server;
+use serde_json::json;
+use std::sync::Arc;
+
+const OWNER_A: &str = "0x1111111111111111111111111111111111111111";
+const OWNER_B: &str = "0x2222222222222222222222222222222222222222";
+const OWNER_C: &str = "0x3333333333333333333333333333333333333333";
+
+fn ids(body: &serde_json::Value) -> Vec<String> {
+    body["data"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["id"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn block_num_from_node() {
+    let node = MockNode::spawn().await;
+    node.set_block_num(4242);
+    assert_eq!(get_block_num(&node.config).await.unwrap(), 4242);
+}
+
+#[tokio::test]
+async fn dragons_list() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (status, body) = get(&app, "/api/v1/dragons").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["success"], true);
+    assert_eq!(ids(&body), ["1", "2", "3", "4", "5", "6"]);
+    assert_eq!(body["pagination"]["records"], 6);
+    assert_eq!(body["pagination"]["pages"], 1);
+
+    let (_, body) = get(&app, "/api/v1/dragons?limit=4&offset=1").await;
+    assert_eq!(ids(&body), ["5", "6"]);
+    assert_eq!(body["pagination"]["pages"], 2);
+    assert_eq!(body["pagination"]["current_page"], 2);
+}
+
+#[tokio::test]
+async fn dragons_by_owner() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    // listed on the market dragons still belong to the seller
+    let (_, body) = get(&app, &format!("/api/v1/dragons?owner={}", OWNER_B)).await;
+    assert_eq!(ids(&body), ["3", "4", "5"]);
+    let (_, body) = get(&app, &format!("/api/v1/dragons?owner={}&stage=0", OWNER_B)).await;
+    assert_eq!(ids(&body), ["3"]);
+    let (_, body) = get(&app, &format!("/api/v1/dragons?owner={}", OWNER_A)).await;
+    assert_eq!(ids(&body), ["1", "2", "6"]);
+    let (status, body) = get(&app, &format!("/api/v1/dragons?owner={}", OWNER_C)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["pagination"]["records"], 0);
+    let (status, _) = get(&app, "/api/v1/dragons?owner=0xdead").await;
+    assert_eq!(status, 400);
+    // ab€cd, not split inside the euro sign
+    let (status, _) = get(&app, "/api/v1/dragons?owner=ab%E2%82%ACcd").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn owner_address_formats() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let bech32 = to_bech32(OWNER_B);
+    assert!(bech32.starts_with("zil1"));
+    for owner in [bech32.clone(), OWNER_B[2..].to_string()] {
+        let (status, body) = get(&app, &format!("/api/v1/dragons?owner={}", owner)).await;
+        assert_eq!(status, 200);
+        assert_eq!(ids(&body), ["3", "4", "5"]);
+        assert_eq!(body["data"][0]["owner"], OWNER_B);
+    }
+    let (_, body) = get(
+        &app,
+        &format!("/api/v1/market?owner={}&bech32=true", bech32),
+    )
+    .await;
+    assert_eq!(ids(&body), ["3", "5"]);
+    assert_eq!(body["data"][0]["owner"], bech32.as_str());
+    let (_, body) = get(&app, "/api/v1/dragons/3?bech32=true").await;
+    assert_eq!(body["data"][0]["owner"], bech32.as_str());
+
+    let (status, _) = get(&app, "/api/v1/market?owner=zil1notanaddress").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn dragon_by_id() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (status, body) = get(&app, "/api/v1/dragons/6").await;
+    assert_eq!(status, 200);
+    let item = &body["data"][0];
+    assert_eq!(item["name"], "Smaug");
+    assert_eq!(item["owner"], OWNER_A);
+    assert_eq!(item["stage"], 1);
+    assert_eq!(item["gen_image"], "77701640294030440241141076065");
+    assert_eq!(item["fights_win"], 1);
+    assert_eq!(item["fights_lose"], 1);
+
+    let (_, body) = get(&app, "/api/v1/dragons/3").await;
+    let item = &body["data"][0];
+    assert_eq!(item["owner"], OWNER_B);
+    assert_eq!(item["stage"], 0);
+    assert_eq!(
+        item["actions"],
+        serde_json::json!([[3, "5000000000000000"], [4, "1"]])
+    );
+
+    let (_, body) = get(&app, "/api/v1/dragons/2").await;
+    assert_eq!(body["data"][0]["wounds"], serde_json::json!(["3", "7"]));
+
+    let (status, body) = get(&app, "/api/v1/dragons/42").await;
+    assert_eq!(status, 404);
+    assert_eq!(body["success"], false);
+}
+
+#[tokio::test]
+async fn market_battle_breed() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (_, body) = get(&app, "/api/v1/market").await;
+    assert_eq!(ids(&body), ["3", "5"]);
+    let (_, body) = get(&app, "/api/v1/market?sort=3").await;
+    assert_eq!(ids(&body), ["5", "3"]);
+    let (_, body) = get(&app, "/api/v1/market?start_price=4000000000000000").await;
+    assert_eq!(ids(&body), ["3"]);
+
+    let (_, body) = get(&app, "/api/v1/battle").await;
+    assert_eq!(ids(&body), ["1"]);
+    assert_eq!(
+        body["data"][0]["actions"],
+        serde_json::json!([[1, "1000000000000000"]])
+    );
+
+    let (_, body) = get(&app, &format!("/api/v1/breed?owner={}", OWNER_B)).await;
+    assert_eq!(ids(&body), ["4"]);
+    let (_, body) = get(&app, &format!("/api/v1/breed?owner={}", OWNER_A)).await;
+    assert_eq!(body["pagination"]["records"], 0);
+}
+
+#[tokio::test]
+async fn bad_pagination() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    // an owner without dragons isn't paged, the limit is still checked
+    let nobody = "0x3333333333333333333333333333333333333333";
+    for path in [
+        String::from("/api/v1/dragons?limit=0"),
+        format!("/api/v1/dragons?owner={}&limit=0", nobody),
+        format!("/api/v1/market?owner={}&limit=0", nobody),
+        String::from("/api/v1/market/history?limit=0"),
+    ] {
+        assert_eq!(get(&app, &path).await.0, 400, "{}", path);
+    }
+    let (status, _) = get(&app, "/api/v1/market?offset=10").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn lineage_in_items() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (_, body) = get(&app, "/api/v1/dragons/3").await;
+    let item = &body["data"][0];
+    assert_eq!(item["parents"][0]["id"], 1);
+    assert_eq!(item["parents"][1]["id"], 4);
+    assert_eq!(
+        item["parents"][1]["url"],
+        "https://res.cloudinary.com/dragonseth/image/upload/4_1.png"
+    );
+    let (_, body) = get(&app, "/api/v1/dragons/1").await;
+    let children: Vec<_> = body["data"][0]["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|x| x["id"].as_u64().unwrap())
+        .collect();
+    assert_eq!(children, [3, 5]);
+    assert_eq!(body["data"][0]["parents"], json!([]));
+}
+
+#[tokio::test]
+async fn dragon_family() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (status, body) = get(&app, "/api/v1/dragons/1/family").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["id"], 1);
+    assert_eq!(body["data"]["children"][1]["id"], 5);
+    assert!(body["data"]["children"][1].get("children").is_none());
+
+    let (_, body) = get(&app, "/api/v1/dragons/1/family?depth=2").await;
+    assert_eq!(body["data"]["children"][1]["children"][0]["id"], 2);
+
+    let (_, body) = get(&app, "/api/v1/dragons/2/family?depth=2").await;
+    let parents = &body["data"]["parents"];
+    assert_eq!(parents[0]["id"], 5);
+    assert_eq!(parents[0]["parents"][0]["id"], 1);
+    assert_eq!(parents[0]["parents"][1]["id"], 6);
+    // walking up never turns back down
+    assert!(parents[0].get("children").is_none());
+
+    let (status, _) = get(&app, "/api/v1/dragons/1/family?depth=0").await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app, "/api/v1/dragons/42/family").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn dragon_genes() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    // 777 03 03 43 31 14 33 44 11 73 1 4 110 158
+    let (status, body) = get(&app, "/api/v1/dragons/1/genes").await;
+    assert_eq!(status, 200);
+    let genes = &body["data"];
+    assert_eq!(genes["scales"], json!({"type": 4, "color": 3, "rarity": 5}));
+    assert_eq!(genes["spins"], json!({"type": 4, "color": 4}));
+    assert_eq!(genes["head"], json!({"type": 1, "rarity": 1}));
+    assert_eq!(genes["claws"], json!({"type": 4}));
+    assert_eq!(genes["color_scheme"]["type"], 110);
+    assert_eq!(genes["mutagen"]["type"], 158);
+    assert_eq!(genes["rarity_sum"], 18);
+    assert_eq!(genes["rarity"], 1);
+
+    let (_, body) = get(&app, "/api/v1/dragons?limit=2").await;
+    assert!(body["data"][0].get("genes").is_none());
+    let (_, body) = get(&app, "/api/v1/dragons?limit=2&expand=genes").await;
+    assert_eq!(body["data"][0]["genes"], genes.clone());
+    assert_eq!(body["data"][1]["genes"]["aura"]["color"], 1);
+    let (_, body) = get(&app, "/api/v1/dragons/1?expand=genes").await;
+    assert_eq!(
+        body["data"][0]["genes"]["rarity"],
+        body["data"][0]["rarity"]
+    );
+
+    let (status, _) = get(&app, "/api/v1/dragons/42/genes").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn dragon_combat() {
+    let node = MockNode::spawn().await;
+    let mut main = node.get("main_state");
+    main["token_gen_battle"]["6"] = json!(format!("{}{}00", "99".repeat(10), "00".repeat(10)));
+    node.set("main_state", main);
+    let app = app(node.app_state().await);
+    let (status, body) = get(&app, "/api/v1/dragons/1/combat").await;
+    assert_eq!(status, 200);
+    let combat = &body["data"];
+    assert_eq!(
+        combat["attack"],
+        json!([80, 14, 79, 16, 2, 74, 10, 13, 22, 99])
+    );
+    assert_eq!(combat["defence"], json!([11, 3, 7, 18, 45, 3, 3, 8, 8, 99]));
+    assert_eq!(combat["attack_total"], 409);
+    assert_eq!(combat["defence_total"], 205);
+    assert_eq!(combat["strength"], 614);
+    assert_eq!(combat["trailing"], 25);
+
+    let (_, body) = get(&app, "/api/v1/dragons/1").await;
+    let item = &body["data"][0];
+    assert_eq!(
+        (&item["strength"], &item["attack"], &item["defence"]),
+        (&json!(614), &json!(409), &json!(205))
+    );
+
+    let (_, body) = get(&app, &format!("/api/v1/dragons?owner={}&sort=4", OWNER_A)).await;
+    assert_eq!(ids(&body), ["6", "2", "1"]);
+    let (_, body) = get(&app, &format!("/api/v1/dragons?owner={}&sort=5", OWNER_A)).await;
+    assert_eq!(ids(&body), ["2", "1", "6"]);
+
+    let (status, _) = get(&app, "/api/v1/dragons/42/combat").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn battle_simulation() {
+    let node = MockNode::spawn().await;
+    let mut state = node.app_state().await;
+    // listed without genes, left out of the waiting list matchups
+    state.battle_id_list.push("42".to_string());
+    let app = app(state);
+    let (status, body) = get(&app, "/api/v1/battle/simulate?attacker=2&defender=1").await;
+    assert_eq!(status, 200);
+    let matchup = &body["data"][0];
+    assert!(matchup.get("win_probability").is_none());
+    assert_eq!(matchup["attacker"]["id"], "2");
+    assert_eq!(matchup["attacker"]["wounds"], json!(["3", "7"]));
+    assert_eq!(matchup["defender"]["id"], "1");
+    let (_, combat) = get(&app, "/api/v1/dragons/1/combat").await;
+    for key in ["attack", "defence", "strength"] {
+        assert_eq!(matchup["defender"][key], combat["data"][key], "{}", key);
+    }
+    assert_eq!(matchup["defender"]["wounds"], json!([]));
+
+    // against the waiting list, dragon 1 is the only one there with genes
+    let (status, body) = get(&app, "/api/v1/battle/simulate?attacker=6").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(body["data"][0]["defender"]["id"], "1");
+
+    let (status, _) = get(&app, "/api/v1/battle/simulate?defender=1").await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app, "/api/v1/battle/simulate?attacker=1&defender=1").await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app, "/api/v1/battle/simulate?attacker=1&defender=42").await;
+    assert_eq!(status, 404);
+}
+
+#[tokio::test]
+async fn breed_preview() {
+    let node = MockNode::spawn().await;
+    let mut state = node.app_state().await;
+    // an egg and an unknown id on the list are left out, not errors
+    state.breed_id_list.push("3".to_string());
+    state.breed_id_list.push("42".to_string());
+    let app = app(state);
+    let (status, body) = get(&app, "/api/v1/breed/preview?father=4&mother=1").await;
+    assert_eq!(status, 200);
+    let pairing = &body["data"][0];
+    assert!(pairing.get("rarity").is_none());
+    assert_eq!(pairing["price"], "2000000000000000");
+    assert_eq!(pairing["father"]["id"], "4");
+    assert_eq!(pairing["mother"]["id"], "1");
+    let (_, genes) = get(&app, "/api/v1/dragons/4/genes").await;
+    assert_eq!(pairing["father"]["genes"], genes["data"]);
+    let (_, combat) = get(&app, "/api/v1/dragons/1/combat").await;
+    assert_eq!(pairing["mother"]["combat"], combat["data"]);
+
+    // against the waiting list, dragon 4 is the only one there that can breed
+    let (status, body) = get(&app, "/api/v1/breed/preview?mother=6").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"].as_array().unwrap().len(), 1);
+    assert_eq!(body["data"][0]["father"]["id"], "4");
+
+    let (status, _) = get(&app, "/api/v1/breed/preview?father=1&mother=1").await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app, "/api/v1/breed/preview?father=42&mother=1").await;
+    assert_eq!(status, 404);
+    // 3 is an egg, 1 isn't on the breed waiting list
+    let (status, _) = get(&app, "/api/v1/breed/preview?father=4&mother=3").await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app, "/api/v1/breed/preview?father=1&mother=6").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn range_and_trait_filters() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let list = |query: &str| {
+        let path = format!("/api/v1/{}", query);
+        let app = &app;
+        async move { ids(&get(app, &path).await.1) }
+    };
+    assert_eq!(
+        list("dragons?limit=10&min_rarity=1&max_rarity=1").await,
+        ["1", "2", "5", "6"]
+    );
+    assert_eq!(
+        list("dragons?limit=10&min_strength=1000").await,
+        ["2", "4", "6"]
+    );
+    assert_eq!(
+        list("dragons?limit=10&max_strength=1000&stage=0").await,
+        ["3"]
+    );
+    assert_eq!(list("dragons?limit=10&stage=0,1").await.len(), 6);
+    assert_eq!(list("dragons?limit=10&horns=6").await, ["2", "6"]);
+    assert_eq!(list("dragons?limit=10&aura_color=3").await, ["1", "4", "5"]);
+    assert_eq!(
+        list("dragons?limit=10&aura_color=3&min_rarity=2").await,
+        ["4"]
+    );
+    assert_eq!(
+        list(&format!(
+            "dragons?limit=10&owner={}&spins=4&spins_color=4",
+            OWNER_A
+        ))
+        .await,
+        ["1"]
+    );
+    assert_eq!(list("market?max_rarity=0").await, ["3"]);
+    assert_eq!(list("breed?min_strength=1000").await, ["4"]);
+    assert!(list("battle?horns=6").await.is_empty());
+
+    let (status, _) = get(&app, "/api/v1/dragons?stage=x").await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app, "/api/v1/dragons?horns=big").await;
+    assert_eq!(status, 400);
+    // head, claws, color_scheme and mutagen have no color digit
+    let (status, _) = get(&app, "/api/v1/dragons?head_color=1").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn multi_key_sort() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let list = |query: &str| {
+        let path = format!("/api/v1/dragons?limit=10&{}", query);
+        let app = &app;
+        async move { ids(&get(app, &path).await.1) }
+    };
+    assert_eq!(list("sort=-rarity").await, ["4", "1", "2", "5", "6", "3"]);
+    assert_eq!(
+        list("sort=-strength,-id").await,
+        ["6", "4", "2", "5", "3", "1"]
+    );
+    assert_eq!(
+        list("sort=strength,-rarity").await,
+        ["1", "5", "3", "4", "2", "6"]
+    );
+    assert_eq!(list("sort=-name").await, ["6", "1", "2", "3", "4", "5"]);
+    assert_eq!(
+        list("sort=-win_ratio,-wins").await,
+        ["1", "6", "2", "3", "4", "5"]
+    );
+    assert_eq!(list("sort=-id").await, ["6", "5", "4", "3", "2", "1"]);
+    // the old numeric sorts
+    assert_eq!(list("sort=1").await, list("sort=-rarity").await);
+    assert_eq!(list("sort=2").await, ["2", "4", "6", "1", "3", "5"]);
+    // equal items don't move between pages
+    let (_, body) = get(&app, "/api/v1/dragons?sort=-strength&limit=2&offset=1").await;
+    assert_eq!(body["data"][0]["id"], "6");
+    assert_eq!(body["data"][1]["id"], "1");
+    let (_, body) = get(&app, "/api/v1/market?sort=-price").await;
+    assert_eq!(body["data"][0]["id"], "3");
+
+    let (status, _) = get(&app, "/api/v1/dragons?sort=-colour").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn search_by_name() {
+    let node = MockNode::spawn().await;
+    node.set(
+        "dragons_name",
+        json!({"dragons_name": {
+            "1": "Ancalagon",
+            "2": "Smaugling",
+            "3": "Toothless",
+            "5": "Big Smaug",
+            "6": "Smaug",
+            "99": "Smaugzilla"
+        }}),
+    );
+    let app = app(node.app_state().await);
+    // prefix matches first, burned 99 is not there
+    let (status, body) = get(&app, "/api/v1/dragons/search?q=SMAUG&limit=10").await;
+    assert_eq!(status, 200);
+    assert_eq!(ids(&body), ["6", "2", "5"]);
+    assert_eq!(body["pagination"]["records"], 3);
+    let (_, body) = get(&app, "/api/v1/dragons/search?q=less").await;
+    assert_eq!(ids(&body), ["3"]);
+    let (_, body) = get(&app, "/api/v1/dragons/search?q=smaug&limit=1&offset=1").await;
+    assert_eq!(ids(&body), ["2"]);
+    let (_, body) = get(&app, "/api/v1/dragons/search?q=smaug&sort=-id").await;
+    assert_eq!(ids(&body), ["6", "5", "2"]);
+
+    let (_, body) = get(&app, "/api/v1/dragons?name=smaug&limit=10").await;
+    assert_eq!(ids(&body), ["2", "5", "6"]);
+    let (_, body) = get(&app, "/api/v1/market?name=Smaug").await;
+    assert_eq!(ids(&body), ["5"]);
+
+    let (status, _) = get(&app, "/api/v1/dragons/search?q=%20").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn cursor_pagination() {
+    let node = MockNode::spawn().await;
+    let app1 = app(node.app_state().await);
+    let (_, body) = get(&app1, "/api/v1/dragons?limit=4").await;
+    let cursor = body["pagination"]["next_cursor"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let (_, body) = get(&app1, &format!("/api/v1/dragons?limit=4&cursor={}", cursor)).await;
+    assert_eq!(body["data"][0]["id"], "5");
+    assert_eq!(body["data"][1]["id"], "6");
+    assert!(body["pagination"].get("next_cursor").is_none());
+
+    // a higher priced order shows up between the two pages
+    let (_, body) = get(&app1, "/api/v1/market?sort=-price&limit=1").await;
+    assert_eq!(body["data"][0]["id"], "3");
+    let cursor = body["pagination"]["next_cursor"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let mut orderbook = node.get("orderbook");
+    orderbook["orderbook"]["3"] = json!({
+        "argtypes": [],
+        "arguments": [OWNER_A, "9000000000000000", "1", "3"],
+        "constructor": "Order"
+    });
+    node.set("orderbook", orderbook);
+    node.set(
+        "dragons_name",
+        json!({"dragons_name": {"1": "Ancalagon", "6": "Smaug"}}),
+    );
+    let app2 = app(node.app_state().await);
+    let (_, body) = get(&app2, "/api/v1/market?sort=-price&limit=1&offset=1").await;
+    assert_eq!(body["data"][0]["id"], "3");
+    let path = format!("/api/v1/market?sort=-price&limit=1&cursor={}", cursor);
+    let (_, body) = get(&app2, &path).await;
+    assert_eq!(body["data"][0]["id"], "5");
+
+    let path = format!("/api/v1/market?sort=price&limit=1&cursor={}", cursor);
+    let (status, _) = get(&app2, &path).await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app2, "/api/v1/market?cursor=zz").await;
+    assert_eq!(status, 400);
+    // relevance order has no cursor
+    let (_, body) = get(&app2, "/api/v1/dragons/search?q=a&limit=1").await;
+    assert!(body["pagination"].get("next_cursor").is_none());
+    let (_, body) = get(&app2, "/api/v1/dragons/search?q=a&limit=1&sort=-id").await;
+    let cursor = body["pagination"]["next_cursor"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    let path = format!(
+        "/api/v1/dragons/search?q=a&limit=1&sort=-id&cursor={}",
+        cursor
+    );
+    let (_, body) = get(&app2, &path).await;
+    assert_eq!(body["data"][0]["id"], "1");
+}
+
+#[tokio::test]
+async fn batch_lookup() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (status, body) = get(&app, "/api/v1/dragons?ids=6,42,1,6&expand=genes").await;
+    assert_eq!(status, 200);
+    assert_eq!(ids(&body), ["6", "1"]);
+    assert_eq!(body["missing"], json!(["42"]));
+    assert_eq!(body["data"][0]["name"], "Smaug");
+    assert!(body["data"][0]["genes"].is_object());
+
+    let (status, body) = post(&app, "/api/v1/dragons/batch", json!(["3", 2, "x"])).await;
+    assert_eq!(status, 200);
+    assert_eq!(ids(&body), ["3", "2"]);
+    assert_eq!(body["missing"], json!(["x"]));
+
+    let (status, _) = post(&app, "/api/v1/dragons/batch", json!({"ids": [1]})).await;
+    assert_eq!(status, 400);
+    let too_many: Vec<u64> = (1..=101).collect();
+    let (status, _) = post(&app, "/api/v1/dragons/batch", json!(too_many)).await;
+    assert_eq!(status, 400);
+    // empty and repeated ids don't count
+    let (status, body) = get(&app, "/api/v1/dragons?ids=1,,2,").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["missing"], json!([]));
+    let repeated = vec!["1"; 150];
+    let (status, body) = post(&app, "/api/v1/dragons/batch", json!(repeated)).await;
+    assert_eq!(status, 200);
+    assert_eq!(ids(&body), ["1"]);
+}
+
+#[tokio::test]
+async fn owner_profile() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (status, body) = get(&app, &format!("/api/v1/owners/{}", OWNER_B)).await;
+    assert_eq!(status, 200);
+    let data = &body["data"];
+    assert_eq!(data["dragons"], 3);
+    assert_eq!(data["stages"], json!({"0": 1, "1": 2}));
+    assert_eq!(data["rarities"], json!({"0": 1, "1": 1, "3": 1}));
+    assert_eq!(
+        data["market"],
+        json!([
+            {"id": "3", "price": "5000000000000000"},
+            {"id": "5", "price": "3000000000000000"}
+        ])
+    );
+    assert_eq!(
+        data["breed"],
+        json!([{"id": "4", "price": "2000000000000000"}])
+    );
+    assert_eq!(data["battle"], json!([]));
+    // 3 at the rarity 0 floor, 4 (no rarity 3 on sale) and 5 at the overall one
+    assert_eq!(data["portfolio_value"], "11000000000000000");
+
+    let (_, body) = get(&app, &format!("/api/v1/owners/{}", OWNER_A)).await;
+    let data = &body["data"];
+    assert_eq!(data["fights_win"], 3);
+    assert_eq!(data["fights_lose"], 3);
+    assert_eq!(
+        data["battle"],
+        json!([{"id": "1", "price": "1000000000000000"}])
+    );
+    assert_eq!(data["portfolio_value"], "9000000000000000");
+
+    let (status, body) = get(&app, &format!("/api/v1/owners/{}", OWNER_C)).await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["dragons"], 0);
+    assert_eq!(body["data"]["portfolio_value"], "0");
+    let (_, body) = get(&app, &format!("/api/v1/owners/{}?bech32=true", OWNER_A)).await;
+    assert_eq!(body["data"]["address"], to_bech32(OWNER_A));
+    let (status, _) = get(&app, "/api/v1/owners/0xdead").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn collection_stats() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let (status, body) = get(&app, "/api/v1/stats").await;
+    assert_eq!(status, 200);
+    let data = &body["data"];
+    assert_eq!(data["total_supply"], "6");
+    assert_eq!(data["holders"], 2);
+    assert_eq!(data["stages"], json!({"0": 1, "1": 5}));
+    assert_eq!(data["rarities"], json!({"0": 1, "1": 4, "3": 1}));
+    assert_eq!(
+        data["market"],
+        json!({
+            "listed": 2,
+            "floor": "3000000000000000",
+            "median": "4000000000000000",
+            "max": "5000000000000000"
+        })
+    );
+    assert_eq!(data["battle"]["listed"], 1);
+    assert_eq!(data["battle"]["median"], "1000000000000000");
+    assert_eq!(data["breed"]["floor"], "2000000000000000");
+}
+
+#[tokio::test]
+async fn events_stream() {
+    let node = MockNode::spawn().await;
+    let mut state = node.app_state().await;
+    state.version = 1;
+    let shared = Shared::new(config(), state.clone());
+    let app = build_server(Arc::clone(&shared));
+    let path = format!("/api/v1/events?owner={}&kind=listed,price_changed", OWNER_B);
+    let (status, mut stream) = open_sse(&app, &path).await;
+    assert_eq!(status, 200);
+
+    let mut next = state.clone();
+    next.version = 2;
+    for (id, price) in [
+        ("1", "1"),
+        ("4", "7000000000000000"),
+        ("5", "2500000000000000"),
+    ] {
+        next.market_id_price
+            .insert(id.to_string(), price.to_string());
+    }
+    next.breed_id_price.remove("4");
+    shared.replace(next);
+    // 1 is of the other owner, the breed delisting is of another kind
+    let (name, data) = next_sse(&mut stream).await.unwrap();
+    assert_eq!(name, "listed");
+    assert_eq!(
+        data,
+        json!({"kind": "listed", "id": "4", "version": 2, "owner": OWNER_B,
+               "list": "market", "to": "7000000000000000"})
+    );
+    let (name, data) = next_sse(&mut stream).await.unwrap();
+    assert_eq!(name, "price_changed");
+    assert_eq!(data["from"], "3000000000000000");
+    assert!(next_sse(&mut stream).await.is_none());
+
+    let (status, _) = get(&app, "/api/v1/events?kind=sold").await;
+    assert_eq!(status, 400);
+    let (status, _) = get(&app, "/api/v1/events/ws").await;
+    assert_eq!(status, 400);
+}
+
+#[tokio::test]
+async fn market_analytics() {
+    let node = MockNode::spawn().await;
+    let shared = Shared::new(config(), node.app_state().await);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let day = 24 * 60 * 60;
+    let sale = |price: &str, ago: u64| Sale {
+        id: String::from("5"),
+        price: price.to_string(),
+        seller: String::from(OWNER_B),
+        buyer: String::from(OWNER_A),
+        block: 100,
+        time: now - ago,
+        rarity: 1,
+        stage: 1,
+    };
+    *shared.sales.write().unwrap() = vec![
+        sale("50", 40 * day),
+        sale("40", 10 * day),
+        sale("20", 2 * day),
+        sale("10", 100),
+        sale("30", 100),
+    ];
+    let app = build_server(Arc::clone(&shared));
+    let (status, body) = get(&app, "/api/v1/market/analytics?interval=1d&candles=3").await;
+    assert_eq!(status, 200);
+    let data = &body["data"];
+    assert_eq!(
+        data["floor"],
+        json!({
+            "overall": "3000000000000000",
+            "by_stage": {"0": "5000000000000000", "1": "3000000000000000"},
+            "by_rarity": {"0": "5000000000000000", "1": "3000000000000000"}
+        })
+    );
+    assert_eq!(data["volume"]["24h"], json!({"volume": "40", "trades": 2}));
+    assert_eq!(data["volume"]["7d"], json!({"volume": "60", "trades": 3}));
+    assert_eq!(data["volume"]["30d"], json!({"volume": "100", "trades": 4}));
+    assert_eq!(data["interval"], day);
+    let candles = data["candles"].as_array().unwrap();
+    assert_eq!(candles.len(), 2);
+    assert_eq!(candles[0]["close"], "20");
+    assert_eq!(
+        candles[1],
+        json!({"start": (now - 100) / day * day, "open": "10", "high": "30", "low": "10",
+               "close": "30", "volume": "40", "trades": 2})
+    );
+
+    for query in [
+        "interval=0h",
+        "interval=soon",
+        "candles=0",
+        "candles=1001",
+        "at_block=100",
+        "at_time=1",
+    ] {
+        let path = format!("/api/v1/market/analytics?{}", query);
+        assert_eq!(get(&app, &path).await.0, 400, "{}", query);
+    }
+}
+
+#[tokio::test]
+async fn client_errors_are_json() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    let error = |code: u16, message: &str| json!({"success": false, "error": {"code": code, "message": message}});
+    // raised by the query parsing helpers
+    let (status, body) = get(&app, "/api/v1/dragons?horns=big").await;
+    assert_eq!((status, body), (400, error(400, "Bad horns filter.")));
+    let (status, body) = get(&app, "/api/v1/dragons?cursor=zz").await;
+    assert_eq!((status, body), (400, error(400, "Bad cursor.")));
+    // and by the query decoder
+    let (status, body) = get(&app, "/api/v1/dragons?limit=many").await;
+    assert_eq!(status, 400);
+    assert_eq!(body["success"], false);
+    // the message is escaped
+    let (_, body) = get(&app, "/api/v1/dragons?sort=a%22b").await;
+    assert_eq!(body["error"]["message"], "Unknown sort key a\"b.");
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use common::*;
+use dragon_api::archive::{self, At, Checkpoint, Found};
+use dragon_api::config::Config;
+use dragon_api::state::Shared;
+use dragon_api::web_api::build_server;
+use serde_json::json;
+use std::sync::Arc;
+
+const OWNER_A: &str = "0x1111111111111111111111111111111111111111";
+const OWNER_B: &str = "0x2222222222222222222222222222222222222222";
+
+fn checkpoint(block: u128, time: u64) -> Checkpoint {
+    Checkpoint { block, time }
+}
+
+#[test]
+fn archive_compaction() {
+    let config = Config {
+        archive_recent_secs: 100,
+        archive_compact_secs: 50,
+        archive_retention_secs: 1000,
+        ..Default::default()
+    };
+    let mut checkpoints = vec![
+        checkpoint(1, 10),   // expired
+        checkpoint(2, 1510), // two in 1500..1550, the last one is kept
+        checkpoint(3, 1540),
+        checkpoint(4, 1560),
+        checkpoint(5, 1890), // the last old one, followed by a recent one
+        checkpoint(6, 1910),
+        checkpoint(7, 1920),
+    ];
+    let removed = archive::compact(&mut checkpoints, 2000, &config);
+    assert_eq!(removed, vec![checkpoint(1, 10), checkpoint(2, 1510)]);
+    let blocks: Vec<u128> = checkpoints.iter().map(|x| x.block).collect();
+    assert_eq!(blocks, vec![3, 4, 5, 6, 7]);
+
+    let archive = archive::Archive::new(checkpoints);
+    let found = |at| match archive.resolve(at) {
+        Some(Found::Stored(x)) => Some(x.block),
+        Some(Found::State(_)) => panic!("nothing is in memory"),
+        None => None,
+    };
+    assert_eq!(found(At::Block(2)), None);
+    assert_eq!(found(At::Block(3)), Some(3));
+    assert_eq!(found(At::Block(100)), Some(7));
+    assert_eq!(found(At::Time(1559)), Some(3));
+    assert_eq!(found(At::Time(1915)), Some(6));
+}
+
+#[tokio::test]
+async fn time_travel_queries() {
+    let mut node = MockNode::spawn().await;
+    let dir = TempFile::new("archive");
+    node.config.archive_path = dir.0.clone();
+    let shared = Shared::new(node.config.clone(), node.app_state().await);
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    archive::record(&shared, 100, now - 7200).await;
+    // 6 goes from A to B in block 101
+    let mut main = node.get("main_state");
+    main["token_owners"]["6"] = json!(OWNER_B);
+    main["tokens_owner_stage"][OWNER_A]
+        .as_object_mut()
+        .unwrap()
+        .remove("6");
+    main["tokens_owner_stage"][OWNER_B]["6"] = json!("1");
+    node.set("main_state", main);
+    shared.replace(node.app_state().await);
+    archive::record(&shared, 101, now).await;
+
+    let app = build_server(Arc::clone(&shared));
+    let owner_of_six = |query: String| {
+        let app = app.clone();
+        async move {
+            let (status, body) = get(&app, &format!("/api/v1/dragons/6{}", query)).await;
+            assert_eq!(status, 200, "{}", query);
+            body["data"][0]["owner"].clone()
+        }
+    };
+    assert_eq!(owner_of_six(String::new()).await, OWNER_B);
+    // read from the archive, then from the cache
+    assert_eq!(owner_of_six(String::from("?at_block=100")).await, OWNER_A);
+    assert_eq!(owner_of_six(String::from("?at_block=100")).await, OWNER_A);
+    assert_eq!(owner_of_six(String::from("?at_block=150")).await, OWNER_B);
+    let query = format!("?at_time={}", now - 3600);
+    assert_eq!(owner_of_six(query).await, OWNER_A);
+
+    let path = format!("/api/v1/dragons?owner={}&at_block=100", OWNER_A);
+    assert_eq!(get(&app, &path).await.1["pagination"]["records"], 3);
+    let path = format!("/api/v1/dragons?owner={}&at_block=101", OWNER_A);
+    assert_eq!(get(&app, &path).await.1["pagination"]["records"], 2);
+    let (status, body) = get(&app, "/api/v1/stats?at_block=100").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"]["total_supply"], "6");
+
+    assert_eq!(get(&app, "/api/v1/dragons/6?at_block=99").await.0, 404);
+    assert_eq!(
+        get(&app, "/api/v1/market?at_block=1&at_time=1").await.0,
+        400
+    );
+    assert_eq!(get(&app, "/api/v1/market?at_block=soon").await.0, 400);
+
+    let (status, body) = get(&app, "/api/v1/archive").await;
+    assert_eq!(status, 200);
+    assert_eq!(
+        body["data"],
+        json!([{"block": 100, "time": now - 7200}, {"block": 101, "time": now}])
+    );
+    assert_eq!(
+        archive::load(&dir.0).unwrap(),
+        vec![checkpoint(100, now - 7200), checkpoint(101, now)]
+    );
+    // the indexes aren't stored, they are built again on read
+    let file = std::path::Path::new(&dir.0).join(format!("100-{}.json", now - 7200));
+    assert!(!std::fs::read_to_string(file)
+        .unwrap()
+        .contains("all_owned_id"));
+    std::fs::remove_dir_all(&dir.0).ok();
+}
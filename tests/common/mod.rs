@@ -0,0 +1,355 @@
+// In-process fake of the Zilliqa JSON-RPC node and the Devex Apollo GraphQL
+// endpoint, serving the contract states from tests/fixtures and the
+// transactions pushed into blocks.
+#![allow(dead_code)]
+
+use dragon_api::config::{Config, Contracts};
+use dragon_api::state::{create, AppState, Shared, SharedState};
+use dragon_api::web_api::build_server;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tide::http::{Method, Request, Response, Url};
+use tide::listener::Listener;
+
+pub const FIXTURES: [&str; 7] = [
+    "main_state",
+    "battle_waiting_list",
+    "wounded_list",
+    "breed_waiting_list",
+    "orderbook",
+    "dragons_name",
+    "events",
+];
+
+#[derive(Clone)]
+pub struct MockNode {
+    pub config: Config,
+    pub block_num: Arc<AtomicU64>,
+    pub fixtures: Arc<Mutex<HashMap<String, Value>>>,
+}
+
+impl MockNode {
+    pub async fn spawn() -> MockNode {
+        let mut fixtures = HashMap::new();
+        for name in FIXTURES {
+            fixtures.insert(name.to_string(), load_fixture(name));
+        }
+        // block number -> txs, see push_tx
+        fixtures.insert(String::from("blocks"), json!({}));
+        let mut node = MockNode {
+            config: Config {
+                rpc_retries: 1,
+                contracts: contracts(),
+                ..config()
+            },
+            block_num: Arc::new(AtomicU64::new(100)),
+            fixtures: Arc::new(Mutex::new(fixtures)),
+        };
+        let mut app = tide::with_state(node.clone());
+        app.at("/").post(rpc);
+        app.at("/apollo").post(apollo);
+        let mut listener = app.bind("127.0.0.1:0").await.expect("mock bind");
+        let base = listener.info()[0].connection().to_string();
+        tokio::spawn(async move { listener.accept().await });
+        node.config.rpc_url = format!("{}/", base);
+        node.config.apollo_url = format!("{}/apollo", base);
+        node
+    }
+    pub fn set(&self, name: &str, value: Value) {
+        self.fixtures
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), value);
+    }
+    pub fn get(&self, name: &str) -> Value {
+        self.fixtures.lock().unwrap()[name].clone()
+    }
+    pub fn push_event(&self, tx: Value) {
+        let mut fixtures = self.fixtures.lock().unwrap();
+        fixtures
+            .get_mut("events")
+            .unwrap()
+            .as_array_mut()
+            .unwrap()
+            .push(tx);
+    }
+    // a tx (with receipt.event_logs) validated in the given block
+    pub fn push_tx(&self, block: u64, tx: Value) {
+        let mut fixtures = self.fixtures.lock().unwrap();
+        let blocks = fixtures.get_mut("blocks").unwrap();
+        let txs = blocks[block.to_string()].take();
+        let mut txs = txs.as_array().cloned().unwrap_or_default();
+        txs.push(tx);
+        blocks[block.to_string()] = Value::Array(txs);
+    }
+    fn block_txs(&self, block: &str) -> Vec<Value> {
+        self.get("blocks")[block]
+            .as_array()
+            .cloned()
+            .unwrap_or_default()
+    }
+    pub fn set_block_num(&self, num: u64) {
+        self.block_num.store(num, Ordering::SeqCst);
+    }
+    pub async fn app_state(&self) -> AppState {
+        create(&self.config, Default::default())
+            .await
+            .expect("fixture state")
+    }
+}
+
+// Unique path in the temp dir, removed when dropped.
+pub struct TempFile(pub String);
+
+impl TempFile {
+    pub fn new(name: &str) -> TempFile {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "dragon-api-{}-{}-{}",
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst),
+            name
+        ));
+        TempFile(path.to_string_lossy().into_owned())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        std::fs::remove_file(&self.0).ok();
+    }
+}
+
+pub fn contracts() -> Contracts {
+    Contracts {
+        main: String::from("0x0000000000000000000000000000000000000001"),
+        battle: String::from("0x0000000000000000000000000000000000000002"),
+        fight: String::from("0x0000000000000000000000000000000000000003"),
+        breed: String::from("0x0000000000000000000000000000000000000004"),
+        market: String::from("0x0000000000000000000000000000000000000005"),
+        name: String::from("0x0000000000000000000000000000000000000006"),
+    }
+}
+
+pub fn load_fixture(name: &str) -> Value {
+    let path = format!(
+        "{}/tests/fixtures/{}.json",
+        env!("CARGO_MANIFEST_DIR"),
+        name
+    );
+    let text = std::fs::read_to_string(&path).expect("fixture");
+    serde_json::from_str(&text).expect("fixture json")
+}
+
+fn same_addr(a: &str, b: &str) -> bool {
+    a.trim_start_matches("0x")
+        .eq_ignore_ascii_case(b.trim_start_matches("0x"))
+}
+
+// Whole state of a contract, merged from its fixtures.
+fn contract_state(node: &MockNode, addr: &str) -> Option<Value> {
+    let contracts = &node.config.contracts;
+    let names: &[&str] = if same_addr(addr, &contracts.main) {
+        &["main_state"]
+    } else if same_addr(addr, &contracts.battle) {
+        &["battle_waiting_list", "wounded_list"]
+    } else if same_addr(addr, &contracts.breed) {
+        &["breed_waiting_list"]
+    } else if same_addr(addr, &contracts.market) {
+        &["orderbook"]
+    } else if same_addr(addr, &contracts.name) {
+        &["dragons_name"]
+    } else {
+        return None;
+    };
+    let mut state = serde_json::Map::new();
+    for name in names {
+        state.extend(node.get(name).as_object().cloned().unwrap_or_default());
+    }
+    Some(Value::Object(state))
+}
+
+// GetSmartContractSubState [addr, field, keys]: null for a missing entry.
+fn sub_state(state: Value, field: &str, keys: &[Value]) -> Value {
+    let mut value = &state[field];
+    for key in keys {
+        value = &value[key.as_str().unwrap_or_default()];
+    }
+    if value.is_null() {
+        return Value::Null;
+    }
+    let mut result = value.clone();
+    for key in keys.iter().rev() {
+        result = json!({ key.as_str().unwrap_or_default(): result });
+    }
+    json!({ field: result })
+}
+
+fn fixture_for(node: &MockNode, method: &str, params: &[Value]) -> Option<Value> {
+    match method {
+        "GetTxBlock" => {
+            let txs = node.block_txs(params.first()?.as_str()?);
+            Some(json!({"header": {"NumTxns": txs.len()}}))
+        }
+        "GetTxnBodiesForTxBlock" => {
+            let txs = node.block_txs(params.first()?.as_str()?);
+            // like the node, an empty block is an error
+            (!txs.is_empty()).then_some(Value::Array(txs))
+        }
+        "GetSmartContractState" => contract_state(node, params.first()?.as_str()?),
+        "GetSmartContractSubState" => {
+            let state = contract_state(node, params.first()?.as_str()?)?;
+            let field = params.get(1)?.as_str()?;
+            let keys = params.get(2).and_then(|x| x.as_array()).cloned();
+            Some(sub_state(state, field, &keys.unwrap_or_default()))
+        }
+        _ => None,
+    }
+}
+
+async fn rpc(mut req: tide::Request<MockNode>) -> tide::Result {
+    let body: Value = req.body_json().await?;
+    let node = req.state();
+    let method = body["method"].as_str().unwrap_or_default();
+    let params = body["params"].as_array().cloned().unwrap_or_default();
+    let result = match method {
+        "GetCurrentMiniEpoch" => Some(json!(node.block_num.load(Ordering::SeqCst).to_string())),
+        _ => fixture_for(node, method, &params),
+    };
+    let response = match result {
+        Some(result) => json!({"id": "1", "jsonrpc": "2.0", "result": result}),
+        None => json!({
+            "error": {"code": -5, "data": null, "message": "Address does not exist"},
+            "id": "1",
+            "jsonrpc": "2.0"
+        }),
+    };
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&response)?)
+        .build())
+}
+
+// txPagination over the "events" fixture filtered by toAddr and _eventname.
+async fn apollo(mut req: tide::Request<MockNode>) -> tide::Result {
+    let body: Value = req.body_json().await?;
+    let vars = &body["variables"];
+    let addr = vars["contractAddr"].as_str().unwrap_or_default();
+    let event = vars["eventName"].as_str().unwrap_or_default();
+    let page = vars["page"].as_u64().unwrap_or(1).max(1) as usize;
+    let per_page = vars["perPage"].as_u64().unwrap_or(1).max(1) as usize;
+    let events = req.state().get("events");
+    let txs: Vec<&Value> = events
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|tx| same_addr(tx["toAddr"].as_str().unwrap_or_default(), addr))
+        .filter(|tx| {
+            tx["receipt"]["event_logs"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .any(|x| x["_eventname"] == event)
+        })
+        .collect();
+    let items: Vec<&Value> = txs
+        .iter()
+        .skip((page - 1) * per_page)
+        .take(per_page)
+        .cloned()
+        .collect();
+    let response = json!({
+        "data": {
+            "txPagination": {
+                "pageInfo": {
+                    "currentPage": page,
+                    "perPage": per_page,
+                    "pageCount": txs.len().div_ceil(per_page),
+                },
+                "items": items,
+            }
+        }
+    });
+    Ok(tide::Response::builder(200)
+        .body(tide::Body::from_json(&response)?)
+        .build())
+}
+
+pub type App = tide::Server<SharedState>;
+
+// Config::default without its files, a test writes only to a TempFile
+pub fn config() -> Config {
+    Config {
+        snapshot_path: String::new(),
+        archive_path: String::new(),
+        webhooks_path: String::new(),
+        sales_path: String::new(),
+        ..Default::default()
+    }
+}
+
+pub fn app(app_state: AppState) -> App {
+    build_server(Shared::new(config(), app_state))
+}
+
+// Runs a request through the dragon-api app without binding a port.
+pub async fn send(app: &App, req: Request) -> (u16, Value) {
+    let mut res: Response = app.respond(req).await.unwrap();
+    let status = res.status() as u16;
+    let text = res.body_string().await.unwrap();
+    let body = serde_json::from_str(&text).unwrap_or(Value::String(text));
+    (status, body)
+}
+
+pub fn request(method: Method, path: &str) -> Request {
+    Request::new(
+        method,
+        Url::parse(&format!("http://localhost{}", path)).unwrap(),
+    )
+}
+
+pub async fn get(app: &App, path: &str) -> (u16, Value) {
+    send(app, request(Method::Get, path)).await
+}
+
+pub async fn post(app: &App, path: &str, body: Value) -> (u16, Value) {
+    let mut req = request(Method::Post, path);
+    req.set_body(tide::Body::from_json(&body).unwrap());
+    send(app, req).await
+}
+
+pub type SseReader = futures_util::io::BufReader<tide::Body>;
+
+// Starts a Server-Sent Events request, the body is read with next_sse.
+pub async fn open_sse(app: &App, path: &str) -> (u16, SseReader) {
+    let mut res: Response = app.respond(request(Method::Get, path)).await.unwrap();
+    let status = res.status() as u16;
+    (status, futures_util::io::BufReader::new(res.take_body()))
+}
+
+// (event name, data) of the next event, None if there is none within a second.
+pub async fn next_sse(reader: &mut SseReader) -> Option<(String, Value)> {
+    use futures_util::io::AsyncBufReadExt;
+    let read = async {
+        let (mut name, mut data) = (String::new(), String::new());
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).await.ok()? == 0 {
+                return None;
+            }
+            let line = line.trim_end();
+            if let Some(x) = line.strip_prefix("event:") {
+                name = x.trim().to_string();
+            } else if let Some(x) = line.strip_prefix("data:") {
+                data.push_str(x.trim());
+            } else if line.is_empty() && !data.is_empty() {
+                return Some((name, serde_json::from_str(&data).ok()?));
+            }
+        }
+    };
+    tokio::time::timeout(std::time::Duration::from_secs(1), read)
+        .await
+        .ok()
+        .flatten()
+}
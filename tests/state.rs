@@ -0,0 +1,455 @@
+mod common;
+
+use common::*;
+use dragon_api::events::{diff, ChangeEvent, EventKind, ListKind};
+use dragon_api::sales::{self, Sale};
+use dragon_api::state::incremental::{next_state, refresh};
+use dragon_api::state::reciver::update_state;
+use dragon_api::state::{create, snapshot, AppState, Shared, StateError};
+use dragon_api::web_api::build_server;
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[tokio::test]
+async fn malformed_main_state() {
+    let node = MockNode::spawn().await;
+    node.set("main_state", json!({"token_owners": 42}));
+    match create(&node.config, Default::default()).await {
+        Err(StateError::Decode(what, _)) => assert_eq!(what, "main state"),
+        other => panic!("expected decode error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn battle_dragon_without_owner() {
+    let node = MockNode::spawn().await;
+    node.set("battle_waiting_list", json!({"waiting_list": {"99": "1"}}));
+    match create(&node.config, Default::default()).await {
+        Err(StateError::Inconsistent(e)) => assert!(e.contains("99")),
+        other => panic!("expected inconsistent error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn malformed_gens_are_taken_as_empty() {
+    let node = MockNode::spawn().await;
+    let mut main = node.get("main_state");
+    main["token_gen_image"]["6"] = json!("7x7");
+    main["token_gen_battle"]["6"] = json!("not a gen");
+    node.set("main_state", main);
+    let state = create(&node.config, Default::default()).await.unwrap();
+    assert_eq!(state.all_id_list.len(), 6);
+    assert_eq!(state.all_id_rarity["6"], 0);
+    assert_eq!(state.all_id_strength["6"], 0);
+}
+
+#[tokio::test]
+async fn node_is_down() {
+    let mut node = MockNode::spawn().await;
+    node.config.rpc_url = String::from("http://127.0.0.1:9/");
+    match create(&node.config, Default::default()).await {
+        Err(StateError::Transport(_)) => {}
+        other => panic!("expected transport error, got {:?}", other.map(|_| ())),
+    }
+}
+
+fn fight_tx(id: &str, winner: &str, loser: &str) -> serde_json::Value {
+    json!({
+        "ID": id,
+        "toAddr": contracts().fight,
+        "receipt": {
+            "event_logs": [{
+                "_eventname": "AfterFightWinLose",
+                "params": [
+                    {"vname": "token_id_winner", "value": winner},
+                    {"vname": "token_id_looser", "value": loser}
+                ]
+            }]
+        }
+    })
+}
+
+#[tokio::test]
+async fn fights_history_over_pages() {
+    let mut node = MockNode::spawn().await;
+    node.config.apollo_page_size = 2;
+    let state = node.app_state().await;
+    assert_eq!(state.all_id_fights["1"], (2, 1));
+    assert_eq!(state.all_id_fights["2"], (0, 1));
+    assert_eq!(state.all_id_fights["6"], (1, 1));
+    assert_eq!(state.fights_cursor.processed, 3);
+    assert_eq!(state.fights_cursor.last_tx, "0xf3");
+}
+
+#[tokio::test]
+async fn fights_history_incremental() {
+    let mut node = MockNode::spawn().await;
+    node.config.apollo_page_size = 2;
+    let state = node.app_state().await;
+    node.push_event(fight_tx("0xf4", "2", "6"));
+    let state = create(&node.config, state.history()).await.unwrap();
+    assert_eq!(state.all_id_fights["2"], (1, 1));
+    assert_eq!(state.all_id_fights["6"], (1, 2));
+    assert_eq!(state.all_id_fights["1"], (2, 1));
+    assert_eq!(state.fights_cursor.processed, 4);
+    // nothing new, nothing counted twice
+    let state = create(&node.config, state.history()).await.unwrap();
+    assert_eq!(state.all_id_fights["6"], (1, 2));
+
+    // the loser is found by name, an event without one is skipped
+    let mut fight = fight_tx("0xf5", "2", "6");
+    let params = &mut fight["receipt"]["event_logs"][0]["params"];
+    params
+        .as_array_mut()
+        .unwrap()
+        .insert(1, json!({"vname": "round", "value": "3"}));
+    node.push_event(fight);
+    let mut fight = fight_tx("0xf6", "6", "2");
+    fight["receipt"]["event_logs"][0]["params"]
+        .as_array_mut()
+        .unwrap()
+        .pop();
+    node.push_event(fight);
+    let state = create(&node.config, state.history()).await.unwrap();
+    assert_eq!(state.all_id_fights["2"], (2, 1));
+    assert_eq!(state.all_id_fights["6"], (1, 3));
+    assert!(!state.all_id_fights.contains_key("3"));
+}
+
+#[tokio::test]
+async fn fights_history_rebuilt_on_mismatch() {
+    let node = MockNode::spawn().await;
+    let mut history = node.app_state().await.history();
+    history.fights_cursor.last_tx = String::from("0xdead");
+    history.fights.insert(String::from("5"), (100, 100));
+    let state = create(&node.config, history).await.unwrap();
+    assert!(!state.all_id_fights.contains_key("5"));
+    assert_eq!(state.all_id_fights["1"], (2, 1));
+}
+
+#[tokio::test]
+async fn snapshot_round_trip() {
+    let node = MockNode::spawn().await;
+    let state = node.app_state().await;
+    let file = TempFile::new("snapshot.json");
+    assert!(snapshot::load(&file.0).unwrap().is_none());
+    snapshot::save(&file.0, 100, &state).unwrap();
+    let (block_num, loaded) = snapshot::load(&file.0).unwrap().unwrap();
+    assert_eq!(block_num, 100);
+    assert_eq!(
+        serde_json::to_value(&loaded).unwrap(),
+        serde_json::to_value(&state).unwrap()
+    );
+    // served without a node at all
+    let app = app(loaded);
+    let (_, body) = get(&app, "/api/v1/dragons/6").await;
+    assert_eq!(body["data"][0]["name"], "Smaug");
+}
+
+#[tokio::test]
+async fn snapshot_of_other_version_is_ignored() {
+    let file = TempFile::new("snapshot.json");
+    std::fs::write(&file.0, r#"{"version":0,"block_num":1,"state":{}}"#).unwrap();
+    assert!(snapshot::load(&file.0).unwrap().is_none());
+}
+
+#[tokio::test]
+async fn refresher_fills_empty_state_and_saves_snapshot() {
+    let mut node = MockNode::spawn().await;
+    let file = TempFile::new("snapshot.json");
+    node.config.snapshot_path = file.0.clone();
+    let shared = Shared::new(node.config.clone(), AppState::default());
+    tokio::spawn(update_state(Arc::clone(&shared), 0));
+    for _ in 0..100 {
+        if !shared.app_state.load().all_id_list.is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(shared.app_state.load().all_id_list.len(), 6);
+    let (block_num, loaded) = snapshot::load(&file.0).unwrap().unwrap();
+    assert_eq!(block_num, 100);
+    assert_eq!(loaded.all_id_list.len(), 6);
+}
+
+const OWNER_A: &str = "0x1111111111111111111111111111111111111111";
+const OWNER_B: &str = "0x2222222222222222222222222222222222222222";
+
+// moves dragon 6 from A to B in the main contract fixture
+fn transfer_six(node: &MockNode) {
+    let mut main = node.get("main_state");
+    main["token_owners"]["6"] = json!(OWNER_B);
+    main["tokens_owner_stage"][OWNER_A]
+        .as_object_mut()
+        .unwrap()
+        .remove("6");
+    main["tokens_owner_stage"][OWNER_B]["6"] = json!("1");
+    main["owned_token_count"][OWNER_A] = json!("2");
+    main["owned_token_count"][OWNER_B] = json!("3");
+    node.set("main_state", main);
+}
+
+fn transfer_tx(id: &str) -> serde_json::Value {
+    main_tx(
+        "TransferSuccess",
+        json!([
+            {"vname": "from", "value": OWNER_A},
+            {"vname": "recipient", "value": OWNER_B},
+            {"vname": "token_id", "value": id}
+        ]),
+    )
+}
+
+fn main_tx(name: &str, params: serde_json::Value) -> serde_json::Value {
+    json!({
+        "ID": "0xa1",
+        "receipt": {
+            "success": true,
+            "event_logs": [{
+                "address": contracts().main,
+                "_eventname": name,
+                "params": params
+            }]
+        }
+    })
+}
+
+#[tokio::test]
+async fn incremental_refresh_applies_block_events() {
+    let node = MockNode::spawn().await;
+    let state = node.app_state().await;
+    transfer_six(&node);
+    // renamed without an event in the range, must not be picked up
+    node.set("dragons_name", json!({"dragons_name": {"6": "Glaurung"}}));
+    node.push_tx(102, transfer_tx("6"));
+    // no token to patch, nothing to resync for either
+    let minter = json!([{"vname": "minter", "value": OWNER_A}]);
+    node.push_tx(103, main_tx("AddMinterSuccess", minter.clone()));
+    let state = refresh(&node.config, &state, 100, 103).await.unwrap();
+    assert_eq!(state.all_id_owner["6"], OWNER_B);
+    assert!(state.all_owned_id[OWNER_B].contains(&String::from("6")));
+    assert!(!state.all_owned_id[OWNER_A].contains(&String::from("6")));
+    assert_eq!(state.main_state.owned_token_count[OWNER_B], "3");
+    assert_eq!(state.id_name["6"], "Smaug");
+
+    // an unknown one can't be applied
+    node.push_tx(104, main_tx("Migrated", minter));
+    match refresh(&node.config, &state, 103, 104).await {
+        Err(StateError::Inconsistent(e)) => assert!(e.contains("Migrated")),
+        other => panic!("expected inconsistent error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn incremental_refresh_falls_back_to_full() {
+    let mut node = MockNode::spawn().await;
+    node.config.max_incremental_blocks = 2;
+    let state = node.app_state().await;
+    node.set("dragons_name", json!({"dragons_name": {"6": "Glaurung"}}));
+    match refresh(&node.config, &state, 100, 103).await {
+        Err(StateError::Inconsistent(_)) => {}
+        other => panic!("expected inconsistent error, got {:?}", other.map(|_| ())),
+    }
+    let (state, full) = next_state(&node.config, &state, 100, 103, false)
+        .await
+        .unwrap();
+    assert!(full);
+    assert_eq!(state.id_name["6"], "Glaurung");
+}
+
+#[tokio::test]
+async fn incremental_refresh_detects_missed_mint() {
+    let node = MockNode::spawn().await;
+    let state = node.app_state().await;
+    transfer_six(&node);
+    let mut main = node.get("main_state");
+    main["total_supply"] = json!("7");
+    node.set("main_state", main);
+    node.push_tx(101, transfer_tx("6"));
+    match refresh(&node.config, &state, 100, 101).await {
+        Err(StateError::Inconsistent(e)) => assert!(e.contains("total_supply")),
+        other => panic!("expected inconsistent error, got {:?}", other.map(|_| ())),
+    }
+}
+
+#[tokio::test]
+async fn no_events_after_a_cold_start() {
+    let node = MockNode::spawn().await;
+    let shared = Shared::new(node.config.clone(), AppState::default());
+    let mut events = shared.events.subscribe();
+    let mut first = node.app_state().await;
+    first.version = 1;
+    shared.replace(first.clone());
+    assert!(events.try_recv().is_err());
+    // from then on changes are sent
+    let mut next = first;
+    next.version = 2;
+    next.breed_id_price.remove("4");
+    shared.replace(next);
+    assert_eq!(events.try_recv().unwrap().kind, EventKind::Delisted);
+}
+
+#[tokio::test]
+async fn state_diff_events() {
+    let node = MockNode::spawn().await;
+    let old = node.app_state().await;
+    let (owner_a, owner_b) = (old.all_id_owner["1"].clone(), old.all_id_owner["3"].clone());
+    let mut new = old.clone();
+    new.version = 7;
+    new.battle_id_price
+        .insert("1".to_string(), "2000000000000000".to_string());
+    new.all_id_wounds
+        .insert("1".to_string(), vec!["9".to_string()]);
+    new.id_name.insert("2".to_string(), "Drogon".to_string());
+    new.main_state.token_stage.remove("3");
+    new.market_id_price.remove("5");
+    new.all_id_owner.insert("6".to_string(), owner_b.clone());
+    new.main_state
+        .token_stage
+        .insert("7".to_string(), "0".to_string());
+    new.all_id_owner.insert("7".to_string(), owner_a.clone());
+
+    let event =
+        |kind, id: &str, owner: &str, list, from: Option<&str>, to: Option<&str>| ChangeEvent {
+            kind,
+            id: id.to_string(),
+            version: 7,
+            owner: owner.to_string(),
+            list,
+            from: from.map(String::from),
+            to: to.map(String::from),
+        };
+    let price = (Some("1000000000000000"), Some("2000000000000000"));
+    assert_eq!(
+        diff(&old, &new),
+        vec![
+            event(
+                EventKind::PriceChanged,
+                "1",
+                &owner_a,
+                Some(ListKind::Battle),
+                price.0,
+                price.1
+            ),
+            event(EventKind::Wounded, "1", &owner_a, None, None, Some("9")),
+            event(
+                EventKind::Renamed,
+                "2",
+                &owner_a,
+                None,
+                None,
+                Some("Drogon")
+            ),
+            event(EventKind::Burn, "3", &owner_b, None, Some(&owner_b), None),
+            event(
+                EventKind::Delisted,
+                "5",
+                &owner_b,
+                Some(ListKind::Market),
+                Some("3000000000000000"),
+                None
+            ),
+            event(
+                EventKind::Transfer,
+                "6",
+                &owner_b,
+                None,
+                Some(&owner_a),
+                Some(&owner_b)
+            ),
+            event(EventKind::Mint, "7", &owner_a, None, None, Some(&owner_a)),
+        ]
+    );
+    assert!(diff(&new, &new).is_empty());
+}
+
+#[tokio::test]
+async fn refresher_records_market_sales() {
+    let mut node = MockNode::spawn().await;
+    let file = TempFile::new("sales.jsonl");
+    node.config.sales_path = file.0.clone();
+    node.config.incremental = false;
+    let mut state = node.app_state().await;
+    state.version = 1;
+    let shared = Shared::new(node.config.clone(), state);
+    // order 2 of B selling 5 for 3e15 is filled by A
+    let mut orderbook = node.get("orderbook");
+    orderbook["orderbook"].as_object_mut().unwrap().remove("2");
+    node.set("orderbook", orderbook);
+    let mut main = node.get("main_state");
+    let market = contracts().market;
+    main["token_owners"]["5"] = json!(OWNER_A);
+    main["tokens_owner_stage"][&market]
+        .as_object_mut()
+        .unwrap()
+        .remove("5");
+    main["tokens_owner_stage"][OWNER_A]["5"] = json!("1");
+    node.set("main_state", main);
+    node.set_block_num(101);
+    tokio::spawn(update_state(Arc::clone(&shared), 100));
+    for _ in 0..100 {
+        if !shared.sales.read().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    let sales = shared.sales.read().unwrap().clone();
+    assert_eq!(sales.len(), 1);
+    let sale = &sales[0];
+    assert_eq!(
+        (
+            sale.id.as_str(),
+            sale.price.as_str(),
+            sale.seller.as_str(),
+            sale.buyer.as_str()
+        ),
+        ("5", "3000000000000000", OWNER_B, OWNER_A)
+    );
+    assert_eq!((sale.block, sale.rarity, sale.stage), (101, 1, 1));
+    assert_eq!(sales::load(&file.0).unwrap(), sales);
+
+    let app = build_server(Arc::clone(&shared));
+    let (status, body) = get(&app, "/api/v1/market/history").await;
+    assert_eq!(status, 200);
+    assert_eq!(body["data"][0]["buyer"], OWNER_A);
+    assert_eq!(body["pagination"]["records"], 1);
+    let (_, body) = get(&app, &format!("/api/v1/market/history?owner={}", OWNER_B)).await;
+    assert_eq!(body["pagination"]["records"], 1);
+    let (_, body) = get(&app, "/api/v1/market/history?min_rarity=2").await;
+    assert_eq!(body["pagination"]["records"], 0);
+    let path = format!("/api/v1/market/history?to={}", sale.time - 1);
+    assert_eq!(get(&app, &path).await.1["pagination"]["records"], 0);
+    let (_, body) = get(&app, "/api/v1/dragons/5/history?stage=1").await;
+    assert_eq!(body["data"][0]["price"], "3000000000000000");
+    let (_, body) = get(&app, "/api/v1/dragons/3/history").await;
+    assert_eq!(body["pagination"]["records"], 0);
+    assert_eq!(get(&app, "/api/v1/dragons/42/history").await.0, 404);
+    // sales aren't archived, from/to bound them
+    for path in [
+        "/api/v1/market/history?at_block=100",
+        "/api/v1/dragons/5/history?at_time=1",
+    ] {
+        assert_eq!(get(&app, path).await.0, 400, "{}", path);
+    }
+}
+
+#[test]
+fn sales_store_skips_a_torn_line() {
+    let file = TempFile::new("torn.jsonl");
+    let sale = Sale {
+        id: String::from("5"),
+        price: String::from("3"),
+        seller: String::from(OWNER_B),
+        buyer: String::from(OWNER_A),
+        block: 101,
+        time: 1,
+        rarity: 1,
+        stage: 1,
+    };
+    sales::append(&file.0, &[sale.clone(), sale.clone()]).unwrap();
+    let mut text = std::fs::read_to_string(&file.0).unwrap();
+    text.push_str("{\"id\":\"6\",\"pri");
+    std::fs::write(&file.0, &text).unwrap();
+    assert_eq!(sales::load(&file.0).unwrap(), vec![sale.clone(), sale]);
+}
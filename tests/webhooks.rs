@@ -0,0 +1,195 @@
+mod common;
+
+use common::*;
+use dragon_api::config::Config;
+use dragon_api::state::Shared;
+use dragon_api::web_api::build_server;
+use dragon_api::webhooks;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tide::listener::Listener;
+
+const SECRET: &str = "0123456789abcdef";
+
+// (signature, body) of the requests, the first `failures` ones are answered
+// 500, every one after `delay`
+#[derive(Clone, Default)]
+struct Receiver {
+    calls: Arc<Mutex<Vec<(String, String)>>>,
+    failures: Arc<AtomicUsize>,
+    delay: Duration,
+}
+
+async fn spawn_receiver(failures: usize, delay: Duration) -> (Receiver, String) {
+    let receiver = Receiver {
+        delay,
+        ..Default::default()
+    };
+    receiver.failures.store(failures, Ordering::SeqCst);
+    let mut app = tide::with_state(receiver.clone());
+    app.at("/hook")
+        .post(|mut req: tide::Request<Receiver>| async move {
+            async_std::task::sleep(req.state().delay).await;
+            let body = req.body_string().await?;
+            let signature = req
+                .header("x-dragon-signature")
+                .map(|x| x.as_str().to_string())
+                .unwrap_or_default();
+            req.state().calls.lock().unwrap().push((signature, body));
+            let failures = &req.state().failures;
+            if failures.load(Ordering::SeqCst) > 0 {
+                failures.fetch_sub(1, Ordering::SeqCst);
+                return Ok(tide::Response::new(500));
+            }
+            Ok(tide::Response::new(200))
+        });
+    let mut listener = app.bind("127.0.0.1:0").await.expect("receiver bind");
+    let url = format!("{}/hook", listener.info()[0].connection());
+    tokio::spawn(async move { listener.accept().await });
+    (receiver, url)
+}
+
+#[tokio::test]
+async fn webhook_delivery_with_retry() {
+    let node = MockNode::spawn().await;
+    let mut state = node.app_state().await;
+    state.version = 1;
+    let file = TempFile::new("webhooks.json");
+    let config = Config {
+        webhooks_path: file.0.clone(),
+        webhook_retry_secs: 0,
+        webhook_allow_private: true,
+        ..config()
+    };
+    let shared = Shared::new(config, state.clone());
+    tokio::spawn(webhooks::run(
+        Arc::clone(&shared),
+        shared.events.subscribe(),
+    ));
+    let app = build_server(Arc::clone(&shared));
+    let (receiver, url) = spawn_receiver(1, Duration::ZERO).await;
+
+    let hook = json!({"url": url, "secret": SECRET, "ids": ["4"], "kinds": ["listed"],
+                      "max_price": "8000000000000000"});
+    let (status, body) = post(&app, "/api/v1/webhooks", hook).await;
+    assert_eq!(status, 200);
+    let id = body["data"]["id"].as_str().unwrap().to_string();
+    assert!(body["data"].get("secret").is_none());
+    // listings over the max price and off the market are not sent
+    let cheap = json!({"url": url, "secret": SECRET, "max_price": "1000"});
+    assert_eq!(post(&app, "/api/v1/webhooks", cheap).await.0, 200);
+
+    let mut next = state.clone();
+    next.version = 2;
+    next.market_id_price
+        .insert("4".to_string(), "7000000000000000".to_string());
+    next.market_id_price
+        .insert("1".to_string(), "7000000000000000".to_string());
+    next.breed_id_price.insert("2".to_string(), "1".to_string());
+    shared.replace(next);
+
+    // answered 500 first, delivered on the retry
+    for _ in 0..50 {
+        if receiver.calls.lock().unwrap().len() >= 2 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let calls = receiver.calls.lock().unwrap().clone();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[0], calls[1]);
+    let (signature, body) = &calls[1];
+    let mut mac = Hmac::<Sha256>::new_from_slice(SECRET.as_bytes()).unwrap();
+    mac.update(body.as_bytes());
+    let expected: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect();
+    assert_eq!(signature, &format!("sha256={}", expected));
+    let payload: Value = serde_json::from_str(body).unwrap();
+    assert_eq!(payload["webhook"], id.as_str());
+    assert_eq!(payload["event"]["kind"], "listed");
+    assert_eq!(payload["event"]["id"], "4");
+
+    let saved = webhooks::load(&file.0).unwrap();
+    assert_eq!(saved.hooks.len(), 2);
+    assert!(saved.queue.is_empty());
+
+    let path = format!("/api/v1/webhooks/{}", id);
+    assert_eq!(get(&app, &path).await.0, 200);
+    let (status, _) = send(&app, request(tide::http::Method::Delete, &path)).await;
+    assert_eq!(status, 200);
+    assert_eq!(get(&app, &path).await.0, 404);
+    assert_eq!(webhooks::load(&file.0).unwrap().hooks.len(), 1);
+}
+
+#[tokio::test]
+async fn slow_webhook_does_not_hold_up_others() {
+    let node = MockNode::spawn().await;
+    let mut state = node.app_state().await;
+    state.version = 1;
+    let file = TempFile::new("slow_webhooks.json");
+    let config = Config {
+        webhooks_path: file.0.clone(),
+        webhook_allow_private: true,
+        ..config()
+    };
+    let shared = Shared::new(config, state.clone());
+    tokio::spawn(webhooks::run(
+        Arc::clone(&shared),
+        shared.events.subscribe(),
+    ));
+    let app = build_server(Arc::clone(&shared));
+    let (slow, slow_url) = spawn_receiver(0, Duration::from_secs(4)).await;
+    let (fast, fast_url) = spawn_receiver(0, Duration::ZERO).await;
+    for url in [slow_url, fast_url] {
+        let hook = json!({"url": url, "secret": SECRET, "ids": ["4"]});
+        assert_eq!(post(&app, "/api/v1/webhooks", hook).await.0, 200);
+    }
+    let mut next = state;
+    next.version = 2;
+    next.breed_id_price.remove("4");
+    shared.replace(next);
+    // sent side by side, the fast one doesn't wait for the slow answer
+    for _ in 0..25 {
+        if !fast.calls.lock().unwrap().is_empty() {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    assert_eq!(fast.calls.lock().unwrap().len(), 1);
+    assert!(slow.calls.lock().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn bad_webhook_registrations() {
+    let node = MockNode::spawn().await;
+    let app = app(node.app_state().await);
+    for hook in [
+        json!({"url": "ftp://example.com", "secret": SECRET}),
+        json!({"url": "http://example.com", "secret": "short"}),
+        json!({"url": "http://example.com", "secret": SECRET, "owner": "0xdead"}),
+        json!({"url": "http://example.com", "secret": SECRET, "kinds": ["sold"]}),
+        json!({"url": "http://example.com", "secret": SECRET, "max_price": "cheap"}),
+        json!({"url": "http://127.0.0.1:8080/hook", "secret": SECRET}),
+        json!({"url": "http://10.0.0.1/hook", "secret": SECRET}),
+        json!({"url": "http://169.254.169.254/latest", "secret": SECRET}),
+        json!({"url": "http://[::1]/hook", "secret": SECRET}),
+        json!({"url": "http://[::ffff:192.168.1.1]/hook", "secret": SECRET}),
+        json!({"url": "http://localhost:8080/hook", "secret": SECRET}),
+    ] {
+        assert_eq!(
+            post(&app, "/api/v1/webhooks", hook.clone()).await.0,
+            400,
+            "{}",
+            hook
+        );
+    }
+}